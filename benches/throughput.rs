@@ -0,0 +1,115 @@
+//! Rows per second for the most common queries, the Rust-counterpart to demo.py
+//!
+//! Run with `cargo bench --features serialize`; the number of rows can be set via
+//! `HMH_BENCH_ROWS`.
+use std::time::{Duration, Instant};
+
+#[path = "../tests/util.rs"]
+mod util;
+use util::init_db;
+
+fn report(name: &str, rows: usize, elapsed: Duration) {
+    println!(
+        "{:<40} {:>10} rows in {:>8.2}ms, {:>12.0} rows/s",
+        name,
+        rows,
+        elapsed.as_secs_f64() * 1000.0,
+        rows as f64 / elapsed.as_secs_f64()
+    );
+}
+
+/// Time a query returning a single value, best of three
+fn bench(con: &rusqlite::Connection, name: &str, rows: usize, sql: &str) -> rusqlite::Result<()> {
+    let mut stmt = con.prepare(sql)?;
+    let mut best = Duration::MAX;
+    for _ in 0..3 {
+        let t = Instant::now();
+        stmt.query_row(rusqlite::params![], |row| {
+            row.get::<_, rusqlite::types::Value>(0)
+        })?;
+        best = best.min(t.elapsed());
+    }
+    report(name, rows, best);
+    Ok(())
+}
+
+fn main() -> rusqlite::Result<()> {
+    let rows = std::env::var("HMH_BENCH_ROWS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(2_000_000usize);
+
+    let con = init_db()?;
+    con.execute(
+        "CREATE TABLE foobar (foo INT NOT NULL, bar INT NOT NULL, baz TEXT NOT NULL)",
+        rusqlite::params![],
+    )?;
+    con.execute(
+        r#"WITH RECURSIVE i(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM i WHERE n + 1 < ?1)
+           INSERT INTO foobar (foo, bar, baz) SELECT n % 1231, n % 1409, HEX(n % 7919) FROM i"#,
+        [rows as i64],
+    )?;
+
+    bench(
+        &con,
+        "COUNT(DISTINCT)",
+        rows,
+        "SELECT COUNT(*) FROM (SELECT DISTINCT foo, bar FROM foobar)",
+    )?;
+    bench(
+        &con,
+        "HYPERMINHASH(INT, INT)",
+        rows,
+        "SELECT HYPERMINHASH(foo, bar) FROM foobar",
+    )?;
+    bench(
+        &con,
+        "HYPERMINHASH(TEXT)",
+        rows,
+        "SELECT HYPERMINHASH(baz) FROM foobar",
+    )?;
+    bench(
+        &con,
+        "HYPERMINHASH() GROUP BY",
+        rows,
+        "SELECT COUNT(*) FROM (SELECT HYPERMINHASH(bar) FROM foobar GROUP BY foo)",
+    )?;
+    bench(
+        &con,
+        "HYPERMINHASH_COLUMNS()",
+        rows,
+        "SELECT HYPERMINHASH_COLUMNS(foo, bar, baz) FROM foobar",
+    )?;
+
+    #[cfg(feature = "serialize")]
+    {
+        bench(
+            &con,
+            "HYPERMINHASH_SERIALIZE(INT, INT)",
+            rows,
+            "SELECT HYPERMINHASH_SERIALIZE(foo, bar) FROM foobar",
+        )?;
+
+        let sketches = 1000;
+        con.execute(
+            r#"CREATE TABLE stats AS
+               SELECT foo, HYPERMINHASH_SERIALIZE(bar) AS data
+               FROM foobar WHERE foo < ?1 GROUP BY foo"#,
+            [sketches],
+        )?;
+        bench(
+            &con,
+            "HYPERMINHASH_UNION()",
+            sketches as usize,
+            "SELECT HYPERMINHASH_UNION(data) FROM stats",
+        )?;
+        bench(
+            &con,
+            "HYPERMINHASH_INTERSECTION(constant)",
+            sketches as usize,
+            r#"SELECT SUM(HYPERMINHASH_INTERSECTION(data, (SELECT data FROM stats WHERE foo = 0)))
+               FROM stats"#,
+        )?;
+    }
+    Ok(())
+}
@@ -8,11 +8,41 @@ fn main() {
         .header("wrapper.h")
         .allowlist_function("sqlite3_aggregate_context")
         .allowlist_function("sqlite3_auto_extension")
+        .allowlist_function("sqlite3_bind_blob")
+        .allowlist_function("sqlite3_bind_int64")
+        .allowlist_function("sqlite3_blob_bytes")
+        .allowlist_function("sqlite3_blob_close")
+        .allowlist_function("sqlite3_blob_open")
+        .allowlist_function("sqlite3_blob_read")
+        .allowlist_function("sqlite3_blob_write")
+        .allowlist_function("sqlite3_column_count")
+        .allowlist_function("sqlite3_column_name")
+        .allowlist_function("sqlite3_column_value")
+        .allowlist_function("sqlite3_context_db_handle")
+        .allowlist_function("sqlite3_declare_vtab")
+        .allowlist_function("sqlite3_errmsg")
         .allowlist_function("sqlite3_errstr")
+        .allowlist_function("sqlite3_finalize")
+        .allowlist_function("sqlite3_free")
+        .allowlist_function("sqlite3_get_auxdata")
+        .allowlist_function("sqlite3_malloc64")
+        .allowlist_function("sqlite3_mprintf")
+        .allowlist_function("sqlite3_prepare_v2")
+        .allowlist_function("sqlite3_realloc64")
+        .allowlist_function("sqlite3_reset")
         .allowlist_function("sqlite3_result_blob")
         .allowlist_function("sqlite3_result_double")
         .allowlist_function("sqlite3_result_error")
+        .allowlist_function("sqlite3_result_error_code")
         .allowlist_function("sqlite3_result_error_nomem")
+        .allowlist_function("sqlite3_result_error_toobig")
+        .allowlist_function("sqlite3_result_int64")
+        .allowlist_function("sqlite3_result_null")
+        .allowlist_function("sqlite3_result_text")
+        .allowlist_function("sqlite3_result_value")
+        .allowlist_function("sqlite3_set_auxdata")
+        .allowlist_function("sqlite3_step")
+        .allowlist_function("sqlite3_user_data")
         .allowlist_function("sqlite3_value_blob")
         .allowlist_function("sqlite3_value_bytes")
         .allowlist_function("sqlite3_value_double")
@@ -20,11 +50,23 @@ fn main() {
         .allowlist_function("sqlite3_value_text")
         .allowlist_function("sqlite3_value_type")
         .allowlist_type("sqlite3_context")
+        .allowlist_type("sqlite3_index_info")
+        .allowlist_type("sqlite3_vtab")
+        .allowlist_type("sqlite3_vtab_cursor")
         .allowlist_var("SQLITE_BLOB")
+        .allowlist_var("SQLITE_CONSTRAINT")
+        .allowlist_var("SQLITE_DONE")
+        .allowlist_var("SQLITE_ERROR")
         .allowlist_var("SQLITE_FLOAT")
+        .allowlist_var("SQLITE_FORMAT")
+        .allowlist_var("SQLITE_INDEX_CONSTRAINT_EQ")
         .allowlist_var("SQLITE_INTEGER")
+        .allowlist_var("SQLITE_INTERNAL")
+        .allowlist_var("SQLITE_MISMATCH")
+        .allowlist_var("SQLITE_NOMEM")
         .allowlist_var("SQLITE_NULL")
         .allowlist_var("SQLITE_OK")
+        .allowlist_var("SQLITE_ROW")
         .allowlist_var("SQLITE_TEXT")
         .generate()
         .expect("Unable to generate bindings");
@@ -0,0 +1,250 @@
+//! Memory for sketches, taken from sqlite's allocator
+//!
+//! Memory from `sqlite3_malloc64()` shows up in `sqlite3_memory_used()` and counts
+//! against `sqlite3_soft_heap_limit64()` and `sqlite3_hard_heap_limit64()`. On top
+//! of that, HYPERMINHASH_MEMORY_LIMIT() caps the memory all sketches of a
+//! connection may hold at once, which is what a GROUP BY over very many groups
+//! runs into. The budget is part of the connection's state, see `settings`.
+//!
+//! A function can't tell which statement it is called by, so the budget is the
+//! connection's, not the statement's: the sketches of all of the connection's
+//! statements share it, whichever statement holds them. Aggregates, sketches
+//! loaded from their arguments, kept as auxiliary data, or built as a result
+//! are all charged to it.
+use std::{
+    borrow, cmp, ffi, mem, ops,
+    os::raw,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use super::bindings::*;
+use super::settings;
+use super::{arguments, HMHError, RawValue};
+
+/// The memory the sketches of a connection may hold, and hold right now
+pub(crate) struct Budget {
+    /// Zero if there is no limit
+    limit: AtomicU64,
+    used: AtomicU64,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            limit: AtomicU64::new(0),
+            used: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Budget {
+    fn charge<'a>(&self, bytes: u64) -> Result<(), HMHError<'a>> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                let used = used.saturating_add(bytes);
+                Some(used).filter(|used| limit == 0 || *used <= limit)
+            })
+            .map(|_| ())
+            .map_err(|_| HMHError::NoMem)
+    }
+
+    fn refund(&self, bytes: u64) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+
+    /// The budget of the connection `ctx` belongs to
+    pub(crate) unsafe fn of_connection(ctx: *mut sqlite3_context) -> Arc<Budget> {
+        Arc::clone(&settings::connection(ctx).budget)
+    }
+
+    /// The budget of the connection `db`, for those without a function's context,
+    /// such as table-valued functions
+    pub(crate) fn of_db(db: *mut sqlite3) -> Option<Arc<Budget>> {
+        settings::connection_of_db(db).map(|connection| Arc::clone(&connection.budget))
+    }
+}
+
+/// A growable buffer of plain values, allocated by sqlite and charged to a budget
+pub(crate) struct Buf<T: Copy> {
+    ptr: *mut T,
+    len: usize,
+    cap: usize,
+    budget: Option<Arc<Budget>>,
+}
+
+impl<T: Copy> Buf<T> {
+    pub(crate) fn new(budget: Option<Arc<Budget>>) -> Self {
+        Self {
+            ptr: ptr::null_mut(),
+            len: 0,
+            cap: 0,
+            budget,
+        }
+    }
+
+    /// A buffer of `len` zeroed values
+    pub(crate) fn zeroed<'a>(
+        len: usize,
+        budget: Option<Arc<Budget>>,
+    ) -> Result<Self, HMHError<'a>> {
+        let mut buf = Self::new(budget);
+        buf.grow_to(len)?;
+        unsafe { ptr::write_bytes(buf.ptr, 0, len) };
+        buf.len = len;
+        Ok(buf)
+    }
+
+    /// A buffer holding a copy of `values`
+    pub(crate) fn from_slice<'a>(
+        values: &[T],
+        budget: Option<Arc<Budget>>,
+    ) -> Result<Self, HMHError<'a>> {
+        let mut buf = Self::new(budget);
+        if !values.is_empty() {
+            buf.grow_to(values.len())?;
+            unsafe { ptr::copy_nonoverlapping(values.as_ptr(), buf.ptr, values.len()) };
+            buf.len = values.len();
+        }
+        Ok(buf)
+    }
+
+    pub(crate) fn budget(&self) -> Option<Arc<Budget>> {
+        self.budget.clone()
+    }
+
+    fn grow_to<'a>(&mut self, cap: usize) -> Result<(), HMHError<'a>> {
+        let bytes = |cap: usize| (cap as u64).saturating_mul(mem::size_of::<T>() as u64);
+        let added = bytes(cap) - bytes(self.cap);
+        if let Some(budget) = &self.budget {
+            budget.charge(added)?;
+        }
+        let p = unsafe { sqlite3_realloc64(self.ptr as *mut ffi::c_void, bytes(cap)) } as *mut T;
+        if p.is_null() {
+            if let Some(budget) = &self.budget {
+                budget.refund(added);
+            }
+            return Err(HMHError::NoMem);
+        }
+        self.ptr = p;
+        self.cap = cap;
+        Ok(())
+    }
+
+    pub(crate) fn push<'a>(&mut self, value: T) -> Result<(), HMHError<'a>> {
+        let len = self.len;
+        self.insert(len, value)
+    }
+
+    pub(crate) fn insert<'a>(&mut self, idx: usize, value: T) -> Result<(), HMHError<'a>> {
+        debug_assert!(idx <= self.len);
+        if self.len == self.cap {
+            self.grow_to((self.cap * 2).max(4))?;
+        }
+        unsafe {
+            ptr::copy(self.ptr.add(idx), self.ptr.add(idx + 1), self.len - idx);
+            self.ptr.add(idx).write(value);
+        }
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl<T: Copy> ops::Deref for Buf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> ops::DerefMut for Buf<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        if self.ptr.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+/// Buffers compare as their contents do, so they can key maps looked up by slice
+impl<T: Copy + Ord> Ord for Buf<T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self[..].cmp(&other[..])
+    }
+}
+
+impl<T: Copy + Ord> PartialOrd for Buf<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Copy + Ord> PartialEq for Buf<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: Copy + Ord> Eq for Buf<T> {}
+
+impl<T: Copy> borrow::Borrow<[T]> for Buf<T> {
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T: Copy> Drop for Buf<T> {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.refund((self.cap * mem::size_of::<T>()) as u64);
+        }
+        unsafe { sqlite3_free(self.ptr as *mut ffi::c_void) };
+    }
+}
+
+/// Set the connection's memory limit for the sketches it holds at once, in bytes;
+/// NULL or zero removes it. Returns the previous limit, NULL if there was none.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_memory_limit(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let limit = match arguments(values, num_values, 0..=1)? {
+            [] => None,
+            [limit] => match RawValue::new(*limit)? {
+                RawValue::Null | RawValue::Int(0) => Some(None),
+                RawValue::Int(i) if i > 0 => Some(Some(i as u64)),
+                other => {
+                    return Err(
+                        HMHError::UnexpectedType("a non-negative INTEGER or NULL", other).at(0),
+                    )
+                }
+            },
+            _ => unreachable!(),
+        };
+        let budget = &settings::connection(ctx).budget;
+        let previous = match limit {
+            Some(limit) => budget.limit.swap(limit.unwrap_or(0), Ordering::Relaxed),
+            None => budget.limit.load(Ordering::Relaxed),
+        };
+        match previous {
+            0 => sqlite3_result_null(ctx),
+            limit => sqlite3_result_int64(ctx, limit as i64),
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,178 @@
+//! Approximate query-planner statistics
+//!
+//! For each index, `sqlite_stat1` holds the number of rows followed by the average
+//! number of rows sharing the same values in the index's first column, first two
+//! columns and so on. We estimate the distinct count of every index-prefix during a
+//! single scan of the table, instead of walking each index as ANALYZE does.
+use std::{collections::BTreeMap, os::raw, sync::Arc};
+
+use super::alloc::Budget;
+use super::bindings::*;
+use super::columns::add_tuple;
+use super::profile::table_arg;
+use super::query::{execute, quote_ident, quote_literal, Statement};
+use super::sparse::SparseSketch;
+use super::{arguments, ArgumentError, HMHError, RawValue};
+
+struct Index {
+    name: String,
+    /// Positions of the index's columns in the table
+    columns: Vec<usize>,
+    unique: bool,
+}
+
+unsafe fn text_column<'a>(stmt: &Statement, col: usize) -> Result<Option<String>, HMHError<'a>> {
+    match RawValue::new(stmt.value(col))? {
+        RawValue::Text(s) => Ok(Some(s.to_owned())),
+        _ => Ok(None),
+    }
+}
+
+/// The table's indexes; partial indexes and those on expressions are left out
+unsafe fn indexes<'a>(
+    db: *mut sqlite3,
+    table: &str,
+    table_columns: &[String],
+) -> Result<Vec<Index>, HMHError<'a>> {
+    let mut list = Statement::prepare(db, &format!("PRAGMA index_list({})", quote_literal(table)))?;
+    let mut indexes = Vec::new();
+    while list.step()? {
+        let name = match text_column(&list, 1)? {
+            Some(name) => name,
+            None => continue,
+        };
+        let unique = matches!(RawValue::new(list.value(2))?, RawValue::Int(1));
+        let partial =
+            list.column_count() > 4 && matches!(RawValue::new(list.value(4))?, RawValue::Int(1));
+        if partial {
+            continue;
+        }
+        let mut info =
+            Statement::prepare(db, &format!("PRAGMA index_info({})", quote_literal(&name)))?;
+        let mut columns = Some(Vec::new());
+        while info.step()? {
+            let position = text_column(&info, 2)?.and_then(|col| {
+                table_columns
+                    .iter()
+                    .position(|c| c.eq_ignore_ascii_case(&col))
+            });
+            columns = columns.zip(position).map(|(mut cols, pos)| {
+                cols.push(pos);
+                cols
+            });
+        }
+        if let Some(columns) = columns {
+            indexes.push(Index {
+                name,
+                columns,
+                unique,
+            });
+        }
+    }
+    Ok(indexes)
+}
+
+/// The `stat`-column, given the number of rows and the distinct count of each prefix
+fn stat(row_count: i64, distinct: &[f64], unique: bool) -> String {
+    let mut stat = row_count.to_string();
+    for (i, d) in distinct.iter().enumerate() {
+        let avg = if unique && i == distinct.len() - 1 {
+            1
+        } else {
+            // ANALYZE rounds up, which would magnify an estimate's error
+            (row_count as f64 / d.clamp(1.0, row_count.max(1) as f64)).round() as i64
+        };
+        stat.push(' ');
+        stat.push_str(&avg.max(1).to_string());
+    }
+    stat
+}
+
+/// Scan the table and write it's approximate statistics, the sketches charged to
+/// the given budget; returns the number of `sqlite_stat1`-rows written
+unsafe fn analyze<'a>(
+    db: *mut sqlite3,
+    budget: Arc<Budget>,
+    table: &str,
+) -> Result<i64, HMHError<'a>> {
+    let mut stmt = Statement::prepare(db, &format!("SELECT * FROM {}", quote_ident(table)))?;
+    let table_columns = (0..stmt.column_count())
+        .map(|col| stmt.column_name(col))
+        .collect::<Vec<_>>();
+    let indexes = indexes(db, table, &table_columns)?;
+    // Indexes sharing a prefix share it's sketch
+    let mut sketches = indexes
+        .iter()
+        .flat_map(|idx| (1..=idx.columns.len()).map(move |len| idx.columns[..len].to_vec()))
+        .map(|prefix| (prefix, SparseSketch::new(Some(budget.clone()), None)))
+        .collect::<BTreeMap<_, _>>();
+    let mut row_count = 0;
+    while stmt.step()? {
+        row_count += 1;
+        let row = (0..table_columns.len())
+            .map(|col| RawValue::new(stmt.value(col)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (prefix, sketch) in sketches.iter_mut() {
+            add_tuple(sketch, &row, prefix)?;
+        }
+    }
+    drop(stmt);
+
+    let mut stats = Vec::new();
+    for idx in &indexes {
+        let distinct = (1..=idx.columns.len())
+            .map(|len| sketches[&idx.columns[..len]].cardinality())
+            .collect::<Vec<_>>();
+        stats.push((
+            Some(idx.name.as_str()),
+            stat(row_count, &distinct, idx.unique),
+        ));
+    }
+    if stats.is_empty() {
+        stats.push((None, row_count.to_string()));
+    }
+
+    // Creates sqlite_stat1 if need be, without analyzing anything
+    execute(db, "ANALYZE sqlite_master")?;
+    execute(
+        db,
+        &format!(
+            "DELETE FROM sqlite_stat1 WHERE tbl = {}",
+            quote_literal(table)
+        ),
+    )?;
+    for (idx, stat) in &stats {
+        execute(
+            db,
+            &format!(
+                "INSERT INTO sqlite_stat1 (tbl, idx, stat) VALUES ({}, {}, {})",
+                quote_literal(table),
+                idx.map_or("NULL".to_owned(), quote_literal),
+                quote_literal(stat)
+            ),
+        )?;
+    }
+    // The query-planner only picks up the new statistics once reloaded
+    execute(db, "ANALYZE sqlite_master")?;
+    Ok(stats.len() as i64)
+}
+
+/// HYPERMINHASH_ANALYZE(table)
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_analyze(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let table = table_arg(args[0]).at(0)?;
+        let written = analyze(
+            sqlite3_context_db_handle(ctx),
+            Budget::of_connection(ctx),
+            table,
+        )?;
+        sqlite3_result_int64(ctx, written);
+        Ok(())
+    })
+}
@@ -0,0 +1,115 @@
+//! Per-column sketches, computed in a single pass
+use std::{os::raw, sync::Arc};
+
+use super::alloc::Budget;
+use super::bindings::*;
+use super::settings::{self, HashKey};
+use super::sparse::{Counter, SparseSketch};
+use super::{aggregate_state_with, arguments, set_text_result, HMHError, RawValue};
+
+/// A sketch per argument-position, charged to the connection's memory budget and
+/// rows hashed with the connection's key as of the first row
+#[derive(Default)]
+pub(crate) struct Columns {
+    key: Option<HashKey>,
+    budget: Option<Arc<Budget>>,
+    pub sketches: Vec<SparseSketch>,
+}
+
+/// Count a single value on it's own; unlike in a tuple, a NULL is not counted at all
+pub(crate) fn add_value<'a>(
+    sketch: &mut impl Counter,
+    value: RawValue,
+) -> Result<(), HMHError<'a>> {
+    if let RawValue::Null = value {
+        return Ok(());
+    }
+    // Hashed as a tuple of one, as `add_row` would
+    sketch.add(&[value][..])
+}
+
+/// Add the tuple of some of a row's columns, as `add_row` would for just those
+pub(crate) fn add_tuple<'a>(
+    sketch: &mut impl Counter,
+    row: &[RawValue],
+    columns: &[usize],
+) -> Result<(), HMHError<'a>> {
+    let tuple = columns
+        .iter()
+        .map(|col| &row[*col])
+        .filter(|v| !matches!(v, RawValue::Null))
+        .collect::<Vec<_>>();
+    sketch.add(tuple)
+}
+
+/// The step-function of HYPERMINHASH_COLUMNS() and HYPERMINHASH_COLUMNS_SERIALIZE(),
+/// adding each argument to the sketch of it's position
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_columns_step(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let values = arguments(values, num_values, 0..=usize::MAX)?;
+        let columns = match aggregate_state_with(ctx, || {
+            Ok(Columns {
+                key: settings::hash_key(ctx)?,
+                budget: Some(Budget::of_connection(ctx)),
+                sketches: Vec::new(),
+            })
+        })? {
+            Some(columns) => columns,
+            None => return Ok(()),
+        };
+        let (key, budget) = (columns.key, &columns.budget);
+        columns
+            .sketches
+            .resize_with(values.len(), || SparseSketch::new(budget.clone(), key));
+        for (sketch, value) in columns.sketches.iter_mut().zip(values) {
+            add_value(sketch, RawValue::new(*value)?)?;
+        }
+        Ok(())
+    })
+}
+
+/// The per-column sketches; none if there were no rows
+pub(crate) unsafe fn take_columns(ctx: *mut sqlite3_context) -> Columns {
+    let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut Columns;
+    if p.is_null() || (*p).is_null() {
+        return Columns::default();
+    }
+    *Box::from_raw(*p)
+}
+
+/// A JSON-object of the given keys and values, in order
+pub(crate) fn json_object(
+    entries: impl IntoIterator<Item = (String, serde_json::Value)>,
+) -> String {
+    let object: serde_json::Value = entries
+        .into_iter()
+        .collect::<serde_json::Map<_, _>>()
+        .into();
+    object.to_string()
+}
+
+/// A JSON-object, keyed by argument-position
+pub(crate) fn columns_object(values: impl IntoIterator<Item = serde_json::Value>) -> String {
+    json_object(
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v)),
+    )
+}
+
+/// Finalize HYPERMINHASH_COLUMNS() into the cardinality per column
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_columns_final(ctx: *mut sqlite3_context) {
+    HMHError::set_ctx(ctx, || {
+        let columns = take_columns(ctx);
+        let cardinalities = columns.sketches.iter().map(|sk| sk.cardinality().into());
+        set_text_result(ctx, &columns_object(cardinalities));
+        Ok(())
+    })
+}
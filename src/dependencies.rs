@@ -0,0 +1,138 @@
+//! Discovering approximate candidate-keys and functional dependencies
+//!
+//! For every set of up to `max_columns` columns A and every other column B, the
+//! tuples A and A,B are counted. A is a key if |A| is about the number of rows;
+//! A determines B (A→B) if |A,B| is about |A|.
+use std::{collections::BTreeMap, ffi};
+
+use super::alloc::Budget;
+use super::bindings::*;
+use super::columns::add_tuple;
+use super::profile::{table_arg, STD_ERROR};
+use super::query::{quote_ident, Statement};
+use super::sparse::SparseSketch;
+use super::vtab::{Cell, Rows, TableDef};
+use super::{ArgumentError, HMHError, RawValue};
+
+/// Estimates within this relative distance of each other are taken to be equal
+const TOLERANCE: f64 = 3.0 * STD_ERROR;
+/// The number of column-sets to count at most, each taking up to a sketch's worth of
+/// memory
+const MAX_SETS: usize = 1024;
+
+fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE * a.max(b)
+}
+
+/// All sets of up to `max` column-indices, each ordered
+fn column_sets<'a>(columns: usize, max: usize) -> Result<Vec<Vec<usize>>, HMHError<'a>> {
+    let mut sets = Vec::new();
+    let mut current = vec![Vec::new()];
+    for _ in 0..max {
+        current = current
+            .iter()
+            .flat_map(|set: &Vec<usize>| {
+                let first = set.last().map_or(0, |c| c + 1);
+                (first..columns).map(move |c| {
+                    let mut set = set.clone();
+                    set.push(c);
+                    set
+                })
+            })
+            .collect();
+        sets.extend(current.iter().cloned());
+        if sets.len() > MAX_SETS {
+            return Err(HMHError::InvalidArgument(
+                "too many column-sets, use a smaller max_columns",
+            ));
+        }
+    }
+    Ok(sets)
+}
+
+unsafe fn dependency_rows<'a>(
+    db: *mut sqlite3,
+    args: &'a [Option<*mut sqlite3_value>],
+) -> Result<Rows, HMHError<'a>> {
+    let table = table_arg(args[0].ok_or(HMHError::TooFewArguments(1))?).at(0)?;
+    let max_columns = match args[1].map(|v| RawValue::new(v)).transpose()? {
+        None => 1,
+        Some(RawValue::Int(i)) if i > 0 => i as usize,
+        Some(RawValue::Int(_)) => {
+            return Err(HMHError::InvalidArgument("max_columns must be positive").at(1))
+        }
+        Some(other) => return Err(HMHError::UnexpectedType("an INTEGER max_columns", other).at(1)),
+    };
+
+    let mut stmt = Statement::prepare(db, &format!("SELECT * FROM {}", quote_ident(table)))?;
+    let columns = stmt.column_count();
+    // The left-hand sides, plus each of them with one more column, charged to the
+    // connection's memory budget
+    let budget = Budget::of_db(db);
+    let mut sketches = column_sets(columns, (max_columns + 1).min(columns))?
+        .into_iter()
+        .map(|set| (set, SparseSketch::new(budget.clone(), None)))
+        .collect::<BTreeMap<_, _>>();
+    let mut row_count = 0;
+    while stmt.step()? {
+        row_count += 1;
+        let row = (0..columns)
+            .map(|col| RawValue::new(stmt.value(col)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (set, sketch) in sketches.iter_mut() {
+            add_tuple(sketch, &row, set)?;
+        }
+    }
+
+    let name = |set: &[usize]| {
+        set.iter()
+            .map(|col| stmt.column_name(*col))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut rows = Vec::new();
+    for (lhs, sketch) in sketches.iter().filter(|(set, _)| set.len() <= max_columns) {
+        let lhs_distinct = sketch.cardinality();
+        let is_key = approx_eq(lhs_distinct, row_count as f64);
+        rows.push(vec![
+            Cell::Text(name(lhs)),
+            Cell::Null,
+            Cell::Float(lhs_distinct),
+            Cell::Null,
+            Cell::Int(row_count),
+            Cell::Int(is_key as i64),
+            Cell::Null,
+        ]);
+        for rhs in (0..columns).filter(|col| !lhs.contains(col)) {
+            let mut combined = lhs.clone();
+            combined.push(rhs);
+            combined.sort_unstable();
+            let combined_distinct = sketches[&combined].cardinality();
+            rows.push(vec![
+                Cell::Text(name(lhs)),
+                Cell::Text(stmt.column_name(rhs)),
+                Cell::Float(lhs_distinct),
+                Cell::Float(combined_distinct),
+                Cell::Int(row_count),
+                Cell::Int(is_key as i64),
+                Cell::Int(approx_eq(lhs_distinct, combined_distinct) as i64),
+            ]);
+        }
+    }
+    Ok(rows)
+}
+
+static DEPENDENCIES_TABLE: TableDef = TableDef {
+    name: "hyperminhash_dependencies",
+    schema: "CREATE TABLE x(lhs TEXT, rhs TEXT, lhs_distinct REAL, combined_distinct REAL, row_count INTEGER, is_key INTEGER, is_dependency INTEGER, tbl HIDDEN, max_columns HIDDEN)",
+    columns: 9,
+    args: 2,
+    required: 1,
+    rows: dependency_rows,
+};
+
+/// One row per set of columns, on it's own and together with each other column
+#[no_mangle]
+pub extern "C" fn hyperminhash_dependencies_vtab() -> *const ffi::c_void {
+    &DEPENDENCIES_TABLE as *const TableDef as *const ffi::c_void
+}
@@ -0,0 +1,184 @@
+//! Per-key sketches within a single aggregate
+use std::{cmp::Ordering, collections::BTreeMap, os::raw, sync::Arc};
+
+use super::alloc::{Budget, Buf};
+use super::bindings::*;
+use super::columns::json_object;
+use super::settings::{self, HashKey};
+use super::sparse::SparseSketch;
+use super::{add_row, aggregate_state_with, arguments, set_text_result, HMHError, RawValue};
+
+/// A sketch per key, charged to the connection's memory budget along with the keys
+/// themselves, and rows hashed with the connection's key as of the first row.
+/// Keys are kept as sqlite orders them: numbers by value, `INTEGER`s and `REAL`s
+/// alike, `TEXT` and `BLOB`s byte by byte.
+#[derive(Default)]
+pub(crate) struct Groups {
+    key: Option<HashKey>,
+    budget: Option<Arc<Budget>>,
+    /// The kind of all keys, as of the first key
+    kind: Option<KeyKind>,
+    numbers: BTreeMap<Number, SparseSketch>,
+    bytes: BTreeMap<Buf<u8>, SparseSketch>,
+}
+
+/// Keys of different kinds could end up as the same JSON-key, e.g. `1` and `'1'`,
+/// so all keys have to be of the same kind
+#[derive(Clone, Copy, PartialEq)]
+enum KeyKind {
+    Number,
+    Text,
+    Blob,
+}
+
+/// A numeric key
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    Real(f64),
+}
+
+/// How an `INTEGER` compares to a `REAL`, without rounding the integer
+fn cmp_int_real(i: i64, r: f64) -> Ordering {
+    const MIN: f64 = -9_223_372_036_854_775_808.0;
+    if r.is_nan() || r < MIN {
+        return Ordering::Greater;
+    }
+    if r >= -MIN {
+        return Ordering::Less;
+    }
+    let whole = r.trunc();
+    i.cmp(&(whole as i64))
+        .then_with(|| 0.0.partial_cmp(&(r - whole)).unwrap_or(Ordering::Equal))
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (*self, *other) {
+            (Number::Int(a), Number::Int(b)) => a.cmp(&b),
+            (Number::Real(a), Number::Real(b)) => {
+                a.partial_cmp(&b).unwrap_or_else(|| a.total_cmp(&b))
+            }
+            (Number::Int(a), Number::Real(b)) => cmp_int_real(a, b),
+            (Number::Real(a), Number::Int(b)) => cmp_int_real(b, a).reverse(),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl Groups {
+    /// The sketch of the key, added if there is none yet; NULL-keys are not
+    /// grouped at all
+    fn sketch<'a>(&mut self, key: RawValue<'a>) -> Result<Option<&mut SparseSketch>, HMHError<'a>> {
+        let kind = match key {
+            RawValue::Null => return Ok(None),
+            RawValue::Int(_) | RawValue::Float(_) => KeyKind::Number,
+            RawValue::Text(_) => KeyKind::Text,
+            RawValue::Blob(_) => KeyKind::Blob,
+        };
+        if *self.kind.get_or_insert(kind) != kind {
+            return Err(HMHError::InvalidArgument(
+                "keys have to be all numbers, all TEXT or all BLOBs",
+            ));
+        }
+        let (budget, hash_key) = (&self.budget, self.key);
+        let new = || SparseSketch::new(budget.clone(), hash_key);
+        let bytes = match key {
+            RawValue::Int(i) => {
+                return Ok(Some(self.numbers.entry(Number::Int(i)).or_insert_with(new)))
+            }
+            RawValue::Float(f) => {
+                let key = Number::Real(f64::from_bits(f));
+                return Ok(Some(self.numbers.entry(key).or_insert_with(new)));
+            }
+            RawValue::Text(s) => s.as_bytes(),
+            RawValue::Blob(b) => b,
+            RawValue::Null => unreachable!(),
+        };
+        // Only copy the key if it is a new one
+        if !self.bytes.contains_key(bytes) {
+            let key = Buf::from_slice(bytes, budget.clone())?;
+            self.bytes.insert(key, new());
+        }
+        Ok(self.bytes.get_mut(bytes))
+    }
+
+    /// The sketch per key, keyed by their JSON-key: numbers as sqlite prints them,
+    /// `TEXT` as is and `BLOB`s as `HEX()` does
+    pub(crate) fn into_sketches(self) -> impl Iterator<Item = (String, SparseSketch)> {
+        let kind = self.kind;
+        let numbers = self.numbers.into_iter().map(|(key, sketch)| match key {
+            Number::Int(i) => (i.to_string(), sketch),
+            Number::Real(r) => (format!("{:?}", r), sketch),
+        });
+        let bytes = self.bytes.into_iter().map(move |(key, sketch)| match kind {
+            Some(KeyKind::Blob) => (key.iter().map(|b| format!("{:02X}", b)).collect(), sketch),
+            _ => (String::from_utf8_lossy(&key).into_owned(), sketch),
+        });
+        numbers.chain(bytes)
+    }
+}
+
+/// The step-function of HYPERMINHASH_GROUPED(key, values...) and it's
+/// serializing variant
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_grouped_step(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let (key, values) = arguments(values, num_values, 1..=usize::MAX)?
+            .split_first()
+            .unwrap();
+        let key = RawValue::new(*key)?;
+        let groups = match aggregate_state_with(ctx, || {
+            Ok(Groups {
+                key: settings::hash_key(ctx)?,
+                budget: Some(Budget::of_connection(ctx)),
+                ..Groups::default()
+            })
+        })? {
+            Some(groups) => groups,
+            None => return Ok(()),
+        };
+        match groups.sketch(key)? {
+            Some(sketch) => add_row(sketch, values),
+            None => Ok(()),
+        }
+    })
+}
+
+/// The per-key sketches; empty if there were no rows
+pub(crate) unsafe fn take_groups(ctx: *mut sqlite3_context) -> Groups {
+    let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut Groups;
+    if p.is_null() || (*p).is_null() {
+        return Groups::default();
+    }
+    *Box::from_raw(*p)
+}
+
+/// Finalize HYPERMINHASH_GROUPED() into the cardinality per key
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_grouped_final(ctx: *mut sqlite3_context) {
+    HMHError::set_ctx(ctx, || {
+        let cardinalities = take_groups(ctx)
+            .into_sketches()
+            .map(|(key, sketch)| (key, sketch.cardinality().into()));
+        set_text_result(ctx, &json_object(cardinalities));
+        Ok(())
+    })
+}
@@ -0,0 +1,149 @@
+//! Counting the elements of JSON-arrays
+//!
+//! Elements are counted as the SQL-values JSON_EACH() would return for them, so a
+//! sketch of a JSON-array's elements is compatible with one of the same values
+//! stored in a column: strings as `TEXT`, numbers as `INTEGER` or `REAL`, booleans
+//! as `1` or `0`. `null`s are not counted, as `NULL`s are not. Nested arrays and
+//! objects are counted as their JSON-text, with each object's keys sorted so that
+//! their order does not matter.
+use std::os::raw;
+
+use super::bindings::*;
+use super::columns::add_value;
+use super::sparse::Counter;
+use super::{arguments, sketch_state, ArgumentError, HMHError, RawValue};
+
+/// A step along a JSON-path
+enum PathStep {
+    Key(String),
+    Index(usize),
+}
+
+/// Parse the subset of SQLite's JSON-paths made of `$`, `.key`, `."key"` and `[N]`
+fn parse_path<'a>(path: &str) -> Result<Vec<PathStep>, HMHError<'a>> {
+    let malformed = || HMHError::InvalidJson(format!("malformed JSON path: {:?}", path));
+    let mut rest = path.strip_prefix('$').ok_or_else(malformed)?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix(".\"") {
+            let end = quoted.find('"').ok_or_else(malformed)?;
+            steps.push(PathStep::Key(quoted[..end].to_owned()));
+            rest = &quoted[end + 1..];
+        } else if let Some(key) = rest.strip_prefix('.') {
+            let end = key.find(&['.', '['][..]).unwrap_or(key.len());
+            if end == 0 {
+                return Err(malformed());
+            }
+            steps.push(PathStep::Key(key[..end].to_owned()));
+            rest = &key[end..];
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']').ok_or_else(malformed)?;
+            steps.push(PathStep::Index(
+                index[..end].parse().map_err(|_| malformed())?,
+            ));
+            rest = &index[end + 1..];
+        } else {
+            return Err(malformed());
+        }
+    }
+    Ok(steps)
+}
+
+/// The value at the path, if any
+fn lookup<'v>(value: &'v serde_json::Value, path: &[PathStep]) -> Option<&'v serde_json::Value> {
+    path.iter().try_fold(value, |v, step| match step {
+        PathStep::Key(key) => v.get(key),
+        PathStep::Index(i) => v.get(i),
+    })
+}
+
+/// The value with all objects' keys sorted
+fn canonical(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Array(a) => a.iter().map(canonical).collect(),
+        serde_json::Value::Object(o) => {
+            let mut members = o.iter().collect::<Vec<_>>();
+            members.sort_unstable_by_key(|(k, _)| *k);
+            members
+                .into_iter()
+                .map(|(k, v)| (k.clone(), canonical(v)))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }
+        other => other.clone(),
+    }
+}
+
+/// Count a single element, as the SQL-value JSON_EACH() would return for it
+fn add_element<'a>(
+    sketch: &mut impl Counter,
+    element: &serde_json::Value,
+) -> Result<(), HMHError<'a>> {
+    match element {
+        serde_json::Value::Null => Ok(()),
+        serde_json::Value::Bool(b) => add_value(sketch, RawValue::Int(*b as i64)),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => add_value(sketch, RawValue::Int(i)),
+            None => add_value(
+                sketch,
+                RawValue::Float(n.as_f64().unwrap_or(f64::NAN).to_bits()),
+            ),
+        },
+        serde_json::Value::String(s) => add_value(sketch, RawValue::Text(s)),
+        nested => add_value(sketch, RawValue::Text(&canonical(nested).to_string())),
+    }
+}
+
+/// Count the elements of an array, the members' values of an object or a
+/// single other value, as JSON_EACH() would iterate them
+pub(crate) fn add_elements<'a>(
+    sketch: &mut impl Counter,
+    value: &serde_json::Value,
+) -> Result<(), HMHError<'a>> {
+    match value {
+        serde_json::Value::Array(a) => a.iter().try_for_each(|e| add_element(sketch, e)),
+        serde_json::Value::Object(o) => o.values().try_for_each(|e| add_element(sketch, e)),
+        other => add_element(sketch, other),
+    }
+}
+
+/// Parse a JSON-text argument; `None` if NULL
+pub(crate) unsafe fn json_arg<'a>(
+    value: *mut sqlite3_value,
+) -> Result<Option<serde_json::Value>, HMHError<'a>> {
+    match RawValue::new(value)? {
+        RawValue::Null => Ok(None),
+        RawValue::Text(s) => serde_json::from_str(s)
+            .map(Some)
+            .map_err(|e| HMHError::InvalidJson(e.to_string())),
+        other => Err(HMHError::UnexpectedType("JSON-text", other)),
+    }
+}
+
+/// The step-function of HYPERMINHASH_JSON(json_text [, path]), using the state and
+/// final-functions of HYPERMINHASH()
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_json_step(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=2)?;
+        let path = match args.get(1).map(|p| RawValue::new(*p)).transpose()? {
+            None => Vec::new(),
+            Some(RawValue::Text(p)) => parse_path(p).at(1)?,
+            Some(other) => return Err(HMHError::UnexpectedType("a JSON-path", other).at(1)),
+        };
+        let sketch = match sketch_state(ctx)? {
+            Some(sketch) => sketch,
+            None => return Ok(()),
+        };
+        if let Some(json) = json_arg(args[0]).at(0)? {
+            if let Some(value) = lookup(&json, &path) {
+                add_elements(sketch, value)?;
+            }
+        }
+        Ok(())
+    })
+}
@@ -7,12 +7,23 @@ mod bindings {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 use bindings::*;
-use std::{ffi, fmt, io, mem, os::raw, slice};
+use std::{convert::TryFrom, ffi, fmt, hash, io, mem, ops::RangeInclusive, os::raw, panic, slice};
 
-use hyperminhash::Sketch;
+use sparse::{Counter, SparseSketch};
 
+mod alloc;
+pub mod analyze;
+pub mod columns;
+pub mod dependencies;
+pub mod grouped;
+pub mod json;
+pub mod profile;
+mod query;
 #[cfg(feature = "serialize")]
 pub mod serialize;
+mod settings;
+mod sparse;
+pub mod vtab;
 
 #[derive(Debug)]
 enum HMHError<'a> {
@@ -20,18 +31,93 @@ enum HMHError<'a> {
     FeatureMissing,
     #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
     ValueIsNotBlob(RawValue<'a>),
+    TooFewArguments(usize),
+    ArgumentCount(RangeInclusive<usize>, usize),
+    UnexpectedType(&'static str, RawValue<'a>),
+    #[cfg(feature = "serialize")]
+    BlobIo(String),
+    #[cfg(feature = "serialize")]
+    InvalidText(String),
+    #[cfg(feature = "serialize")]
+    Corrupt(String),
+    #[cfg(feature = "serialize")]
+    Suspicious(String),
+    #[cfg(feature = "serialize")]
+    Integrity(&'static str),
+    #[cfg(feature = "serialize")]
+    HashKey(&'static str),
+    #[cfg(feature = "serialize")]
+    NoHashKey,
+    #[cfg(feature = "serialize")]
+    UnknownTextFormat(RawValue<'a>),
+    #[cfg(feature = "serialize")]
+    Timeline(&'static str),
+    InvalidArgument(&'static str),
+    InvalidJson(String),
+    Query(String),
     UnknownValueType,
+    NoMem,
     Io(io::Error),
+    Panic(String),
+    /// The error is due to the argument at the given position
+    Argument(usize, Box<HMHError<'a>>),
+    /// The error is due to the value in the row of the given rowid
+    #[cfg(feature = "serialize")]
+    Row(i64, Box<HMHError<'a>>),
 }
 impl<'a> HMHError<'a> {
+    /// Run `f`, reporting an error as the function's result. The function is named
+    /// by the user-data given to it in shim.c.
     unsafe fn set_ctx<F: FnOnce() -> Result<(), Self>>(ctx: *mut sqlite3_context, f: F) {
-        if let Err(e) = f() {
-            let err_msg = e.to_string();
+        let name = &settings::function(ctx).name;
+        Self::set_ctx_named(ctx, &name.to_string_lossy(), f)
+    }
+
+    unsafe fn set_ctx_named<F: FnOnce() -> Result<(), Self>>(
+        ctx: *mut sqlite3_context,
+        name: &str,
+        f: F,
+    ) {
+        if let Err(e) = catch_panic(f) {
+            if let HMHError::NoMem = e {
+                sqlite3_result_error_nomem(ctx);
+                return;
+            }
+            let err_msg = format!("{}(): {}", name, e);
             sqlite3_result_error(
                 ctx,
                 err_msg.as_bytes().as_ptr() as *const raw::c_char,
                 err_msg.as_bytes().len() as raw::c_int,
             );
+            // Keeps the message set above
+            sqlite3_result_error_code(ctx, e.code() as raw::c_int);
+        }
+    }
+
+    /// Attribute the error to the argument at index `idx`
+    fn at(self, idx: usize) -> Self {
+        match self {
+            HMHError::NoMem | HMHError::Panic(_) | HMHError::Argument(..) => self,
+            e => HMHError::Argument(idx, Box::new(e)),
+        }
+    }
+
+    /// The result-code reported to sqlite
+    fn code(&self) -> u32 {
+        match self {
+            HMHError::ValueIsNotBlob(_) | HMHError::UnexpectedType(..) => SQLITE_MISMATCH,
+            // Not SQLITE_CORRUPT, which would claim the database itself is damaged
+            #[cfg(feature = "serialize")]
+            HMHError::InvalidText(_)
+            | HMHError::Corrupt(_)
+            | HMHError::Suspicious(_)
+            | HMHError::Integrity(_) => SQLITE_FORMAT,
+            HMHError::NoMem => SQLITE_NOMEM,
+            HMHError::Panic(_) => SQLITE_INTERNAL,
+            HMHError::Argument(_, e) => e.code(),
+            #[cfg(feature = "serialize")]
+            HMHError::Row(_, e) => e.code(),
+            _ => SQLITE_ERROR,
         }
     }
 }
@@ -41,13 +127,86 @@ impl<'a> fmt::Display for HMHError<'a> {
         match self {
             #[cfg(not(feature = "serialize"))]
             HMHError::FeatureMissing => write!(f, "This function is unavailable because sqlite3_hyperminhash was compiled without the `serialize`-feature."),
-            HMHError::ValueIsNotBlob(v) => write!(f, "value is not of type BLOB: {:?}", v),
+            HMHError::ValueIsNotBlob(v) => write!(f, "expected a BLOB or TEXT, found {}", v),
+            HMHError::TooFewArguments(n) => write!(f, "function requires at least {} argument(s)", n),
+            HMHError::ArgumentCount(expected, n) if *expected.end() == usize::MAX => write!(f, "function requires at least {} argument(s), found {}", expected.start(), n),
+            HMHError::ArgumentCount(expected, n) if expected.start() == expected.end() => write!(f, "function requires {} argument(s), found {}", expected.start(), n),
+            HMHError::ArgumentCount(expected, n) => write!(f, "function requires {} to {} arguments, found {}", expected.start(), expected.end(), n),
+            HMHError::UnexpectedType(expected, v) => write!(f, "expected {}, found {}", expected, v),
+            #[cfg(feature = "serialize")]
+            HMHError::BlobIo(e) => write!(f, "blob-IO failed: {}", e),
+            #[cfg(feature = "serialize")]
+            HMHError::InvalidText(e) => write!(f, "invalid text-encoded sketch: {}", e),
+            #[cfg(feature = "serialize")]
+            HMHError::Corrupt(e) => write!(f, "malformed value: {}", e),
+            #[cfg(feature = "serialize")]
+            HMHError::Suspicious(e) => write!(f, "suspicious sketch: {}", e),
+            #[cfg(feature = "serialize")]
+            HMHError::Integrity(e) => write!(f, "integrity-check failed: {}", e),
+            #[cfg(feature = "serialize")]
+            HMHError::HashKey(e) => write!(f, "hash-key mismatch: {}", e),
+            #[cfg(feature = "serialize")]
+            HMHError::NoHashKey => write!(f, "no hash-key is set"),
+            #[cfg(feature = "serialize")]
+            HMHError::UnknownTextFormat(v) => write!(f, "unknown text-format, expected 'base64' or 'json', found {}", v),
+            #[cfg(feature = "serialize")]
+            HMHError::Timeline(e) => write!(f, "timeline-error: {}", e),
+            HMHError::InvalidArgument(e) => write!(f, "invalid argument: {}", e),
+            HMHError::InvalidJson(e) => write!(f, "invalid JSON: {}", e),
+            HMHError::Query(e) => write!(f, "query failed: {}", e),
             HMHError::Io(e) => write!(f, "IO-error in hyperminhash: {}", e),
-            HMHError::UnknownValueType => write!(f, "Unkown value-type from sqlite")
+            HMHError::UnknownValueType => write!(f, "Unkown value-type from sqlite"),
+            HMHError::NoMem => write!(f, "out of memory"),
+            HMHError::Panic(e) => write!(f, "internal error in hyperminhash: {}", e),
+            HMHError::Argument(idx, e) => write!(f, "argument {}: {}", idx + 1, e),
+            #[cfg(feature = "serialize")]
+            HMHError::Row(rowid, e) => write!(f, "row {}: {}", rowid, e),
         }
     }
 }
 
+/// Attribute the error of a result to the argument at index `idx`
+trait ArgumentError {
+    fn at(self, idx: usize) -> Self;
+}
+
+impl<'a, T> ArgumentError for Result<T, HMHError<'a>> {
+    fn at(self, idx: usize) -> Self {
+        self.map_err(|e| e.at(idx))
+    }
+}
+
+/// Run `f`, turning a panic into an error instead of unwinding into sqlite
+fn catch_panic<'a, T>(f: impl FnOnce() -> Result<T, HMHError<'a>>) -> Result<T, HMHError<'a>> {
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = if let Some(s) = payload.downcast_ref::<&str>() {
+            (*s).to_owned()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_owned()
+        };
+        Err(HMHError::Panic(msg))
+    })
+}
+
+/// The arguments of a function, checking their number against what shim.c declares
+unsafe fn arguments<'a, 'b>(
+    values: *mut *mut sqlite3_value,
+    num_values: raw::c_int,
+    expected: RangeInclusive<usize>,
+) -> Result<&'b [*mut sqlite3_value], HMHError<'a>> {
+    let n = num_values.max(0) as usize;
+    if !expected.contains(&n) || (n > 0 && values.is_null()) {
+        return Err(HMHError::ArgumentCount(expected, n));
+    }
+    Ok(if n == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(values, n)
+    })
+}
+
 impl<'a> From<io::Error> for HMHError<'a> {
     fn from(e: io::Error) -> Self {
         HMHError::Io(e)
@@ -63,6 +222,24 @@ enum RawValue<'a> {
     Blob(&'a [u8]),
 }
 
+/// Describes the value by it's type, showing only short values in full
+impl<'a> fmt::Display for RawValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        const MAX_CHARS: usize = 32;
+        match self {
+            RawValue::Null => write!(f, "NULL"),
+            RawValue::Int(i) => write!(f, "INTEGER {}", i),
+            RawValue::Float(bits) => write!(f, "REAL {}", f64::from_bits(*bits)),
+            RawValue::Text(s) if s.chars().count() > MAX_CHARS => {
+                let prefix = s.chars().take(MAX_CHARS).collect::<String>();
+                write!(f, "TEXT '{}'... ({} bytes)", prefix, s.len())
+            }
+            RawValue::Text(s) => write!(f, "TEXT '{}'", s),
+            RawValue::Blob(b) => write!(f, "BLOB of {} bytes", b.len()),
+        }
+    }
+}
+
 impl<'a> RawValue<'a> {
     unsafe fn new(value: *mut sqlite3_value) -> Result<Self, HMHError<'a>> {
         match sqlite3_value_type(value) as u32 {
@@ -74,7 +251,10 @@ impl<'a> RawValue<'a> {
             })),
             SQLITE_TEXT => {
                 let s = sqlite3_value_text(value);
-                assert!(!s.is_null());
+                if s.is_null() {
+                    // sqlite failed to convert the value to UTF8
+                    return Err(HMHError::NoMem);
+                }
                 let s = std::ffi::CStr::from_ptr(s as *const raw::c_char);
                 // We explicitely told sqlite3 that we want UTF8-data in shim.c!
                 Ok(RawValue::Text(std::str::from_utf8_unchecked(s.to_bytes())))
@@ -94,14 +274,6 @@ impl<'a> RawValue<'a> {
             _ => Err(HMHError::UnknownValueType),
         }
     }
-
-    #[cfg(feature = "serialize")]
-    fn as_blob(self) -> Result<&'a [u8], HMHError<'a>> {
-        match self {
-            RawValue::Blob(b) => Ok(b),
-            other => Err(HMHError::ValueIsNotBlob(other)),
-        }
-    }
 }
 
 /// Used by tests to auto-load itself into sqlite
@@ -132,6 +304,97 @@ pub unsafe extern "C" fn sqlite3_sqlitehyperminhash_init(
     init_shim(db, pzErrMsg, pApi)
 }
 
+/// SQLITE_TRANSIENT, sqlite makes its own copy
+unsafe fn transient() -> Option<unsafe extern "C" fn(*mut ffi::c_void)> {
+    Some(mem::transmute::<
+        isize,
+        unsafe extern "C" fn(*mut ffi::c_void),
+    >(-1))
+}
+
+unsafe fn set_text_result(ctx: *mut sqlite3_context, text: &str) {
+    match raw::c_int::try_from(text.len()) {
+        Ok(len) => sqlite3_result_text(ctx, text.as_ptr() as *const raw::c_char, len, transient()),
+        Err(_) => sqlite3_result_error_toobig(ctx),
+    }
+}
+
+/// The tuple of values to count as a row, ignoring NULLs as DISTINCT does.
+///
+/// Hashes exactly as a `Vec<RawValue>` of the non-NULL values would, straight from
+/// sqlite's values instead of collecting them first.
+struct Row<'a> {
+    values: &'a [*mut sqlite3_value],
+    len: usize,
+}
+
+impl<'a> Row<'a> {
+    unsafe fn new<'e>(values: &'a [*mut sqlite3_value]) -> Result<Self, HMHError<'e>> {
+        let mut len = 0;
+        for v in values {
+            match sqlite3_value_type(*v) as u32 {
+                SQLITE_NULL => {}
+                SQLITE_INTEGER | SQLITE_FLOAT | SQLITE_TEXT | SQLITE_BLOB => len += 1,
+                _ => return Err(HMHError::UnknownValueType),
+            }
+        }
+        Ok(Self { values, len })
+    }
+}
+
+impl<'a> hash::Hash for Row<'a> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        // As a slice does, the length goes first
+        self.len.hash(state);
+        for v in self.values {
+            // All types have been checked in `Row::new()`
+            match unsafe { RawValue::new(*v) } {
+                Ok(RawValue::Null) | Err(_) => {}
+                Ok(v) => v.hash(state),
+            }
+        }
+    }
+}
+
+/// Add the tuple of values to the sketch
+unsafe fn add_row<'a>(
+    sketch: &mut impl Counter,
+    values: &[*mut sqlite3_value],
+) -> Result<(), HMHError<'a>> {
+    sketch.add(Row::new(values)?)
+}
+
+/// The aggregate's state, created by `init` on the first row; `None` if out of
+/// memory, which has been reported already
+unsafe fn aggregate_state_with<'a, 'e, T>(
+    ctx: *mut sqlite3_context,
+    init: impl FnOnce() -> Result<T, HMHError<'e>>,
+) -> Result<Option<&'a mut T>, HMHError<'e>> {
+    let p = sqlite3_aggregate_context(ctx, mem::size_of::<*mut T>() as raw::c_int) as *mut *mut T;
+    if p.is_null() {
+        sqlite3_result_error_nomem(ctx);
+        return Ok(None);
+    }
+    if (*p).is_null() {
+        *p = Box::into_raw(Box::new(init()?));
+    }
+    Ok(Some(&mut **p))
+}
+
+/// The state of the aggregates counting into a `SparseSketch`, charged to the
+/// connection's memory budget and hashing rows with the function's key
+unsafe fn sketch_state<'a, 'e>(
+    ctx: *mut sqlite3_context,
+) -> Result<Option<&'a mut SparseSketch>, HMHError<'e>> {
+    aggregate_state_with(ctx, || {
+        let key = settings::hash_key(ctx)?;
+        Ok(SparseSketch::new(
+            Some(alloc::Budget::of_connection(ctx)),
+            key,
+        ))
+    })
+}
+
 /// The step-function, called for each row
 #[no_mangle]
 pub unsafe extern "C" fn hyperminhash_step(
@@ -140,37 +403,29 @@ pub unsafe extern "C" fn hyperminhash_step(
     values: *mut *mut sqlite3_value,
 ) {
     HMHError::set_ctx(ctx, || {
-        let p = sqlite3_aggregate_context(ctx, mem::size_of::<*mut Sketch>() as raw::c_int)
-            as *mut *mut Sketch;
-        if p.is_null() {
-            sqlite3_result_error_nomem(ctx);
-            return Ok(());
-        }
-        if (*p).is_null() {
-            *p = Box::into_raw(Box::new(Sketch::default()));
+        let args = arguments(values, num_values, 0..=usize::MAX)?;
+        match sketch_state(ctx)? {
+            Some(sketch) => add_row(sketch, args),
+            None => Ok(()),
         }
-        let sketch = &mut **p;
-        let args: Result<Vec<_>, _> = slice::from_raw_parts(values, num_values as usize)
-            .iter()
-            .filter_map(|v| match RawValue::new(*v) {
-                Ok(RawValue::Null) => None,
-                other => Some(other),
-            })
-            .collect();
-        sketch.add(args?);
-        Ok(())
     })
 }
 
 /// Finalize the aggregate by computing the cardinality
 #[no_mangle]
 pub unsafe extern "C" fn hyperminhash_final(ctx: *mut sqlite3_context) {
-    let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut Sketch;
-    if p.is_null() {
-        sqlite3_result_double(ctx, 0.0);
-        return;
-    }
-    sqlite3_result_double(ctx, Box::from_raw(*p).cardinality());
+    HMHError::set_ctx(ctx, || {
+        let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut SparseSketch;
+        // No rows, or the step-function failed to allocate the sketch
+        if p.is_null() || (*p).is_null() {
+            sqlite3_result_double(ctx, 0.0);
+            return Ok(());
+        }
+        let sketch = Box::from_raw(*p);
+        *p = std::ptr::null_mut();
+        sqlite3_result_double(ctx, sketch.cardinality());
+        Ok(())
+    })
 }
 
 #[cfg(not(feature = "serialize"))]
@@ -182,6 +437,11 @@ pub mod serialize_stub {
         HMHError::set_ctx(ctx, || Err(HMHError::FeatureMissing))
     }
 
+    #[no_mangle]
+    pub unsafe extern "C" fn hyperminhash_timeline_final(ctx: *mut sqlite3_context) {
+        HMHError::set_ctx(ctx, || Err(HMHError::FeatureMissing))
+    }
+
     macro_rules! no_such_func {
         ($name:ident) => {
             #[no_mangle]
@@ -200,4 +460,56 @@ pub mod serialize_stub {
     no_such_func!(hyperminhash_add);
     no_such_func!(hyperminhash_union_step);
     no_such_func!(hyperminhash_intersection);
+    no_such_func!(hyperminhash_info);
+    no_such_func!(hyperminhash_to_text);
+    no_such_func!(hyperminhash_from_text);
+    no_such_func!(hyperminhash_insert);
+    no_such_func!(hyperminhash_insert_inplace);
+    no_such_func!(hyperminhash_timeline_step);
+    no_such_func!(hyperminhash_timeline_union_step);
+    no_such_func!(hyperminhash_timeline_add);
+    no_such_func!(hyperminhash_range);
+    no_such_func!(hyperminhash_timeline_expire);
+    no_such_func!(hyperminhash_timeline_compact);
+    no_such_func!(hyperminhash_from_json);
+    no_such_func!(hyperminhash_validate);
+    no_such_func!(hyperminhash_strict);
+    no_such_func!(hyperminhash_checksum);
+    no_such_func!(hyperminhash_sign);
+    no_such_func!(hyperminhash_verify);
+    no_such_func!(hyperminhash_hmac_key);
+    no_such_func!(hyperminhash_hash_key);
+    no_such_func!(hyperminhash_upgrade);
+    no_such_func!(hyperminhash_upgrade_column);
+
+    #[no_mangle]
+    pub unsafe extern "C" fn hyperminhash_columns_serialize_final(ctx: *mut sqlite3_context) {
+        HMHError::set_ctx(ctx, || Err(HMHError::FeatureMissing))
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn hyperminhash_grouped_serialize_final(ctx: *mut sqlite3_context) {
+        HMHError::set_ctx(ctx, || Err(HMHError::FeatureMissing))
+    }
+
+    unsafe fn no_such_rows<'a>(
+        _db: *mut sqlite3,
+        _args: &'a [Option<*mut sqlite3_value>],
+    ) -> Result<vtab::Rows, HMHError<'a>> {
+        Err(HMHError::FeatureMissing)
+    }
+
+    static REGISTERS_TABLE: vtab::TableDef = vtab::TableDef {
+        name: "hyperminhash_registers",
+        schema: "CREATE TABLE x(idx INTEGER, lz INTEGER, minhash INTEGER, sketch HIDDEN)",
+        columns: 4,
+        args: 1,
+        required: 1,
+        rows: no_such_rows,
+    };
+
+    #[no_mangle]
+    pub extern "C" fn hyperminhash_registers_vtab() -> *const ffi::c_void {
+        &REGISTERS_TABLE as *const vtab::TableDef as *const ffi::c_void
+    }
 }
@@ -0,0 +1,95 @@
+//! Profiling all columns of a table in a single scan
+use std::ffi;
+
+use super::alloc::Budget;
+use super::bindings::*;
+use super::columns::add_value;
+use super::query::{quote_ident, Statement};
+use super::sparse::SparseSketch;
+use super::vtab::{Cell, Rows, TableDef};
+use super::{ArgumentError, HMHError, RawValue};
+
+/// The relative standard error of a sketch's estimate, 1.04 / sqrt(2^14)
+pub(crate) const STD_ERROR: f64 = 0.008125;
+
+/// The sketch is charged to the connection's memory budget
+struct ColumnProfile {
+    sketch: SparseSketch,
+    nulls: i64,
+    integers: i64,
+    reals: i64,
+    texts: i64,
+    blobs: i64,
+}
+
+/// The name of the table to scan
+pub(crate) unsafe fn table_arg<'a>(value: *mut sqlite3_value) -> Result<&'a str, HMHError<'a>> {
+    match RawValue::new(value)? {
+        RawValue::Text(t) => Ok(t),
+        other => Err(HMHError::UnexpectedType("a table-name", other)),
+    }
+}
+
+unsafe fn profile_rows<'a>(
+    db: *mut sqlite3,
+    args: &'a [Option<*mut sqlite3_value>],
+) -> Result<Rows, HMHError<'a>> {
+    let table = table_arg(args[0].ok_or(HMHError::TooFewArguments(1))?).at(0)?;
+    let mut stmt = Statement::prepare(db, &format!("SELECT * FROM {}", quote_ident(table)))?;
+    let budget = Budget::of_db(db);
+    let mut profiles = (0..stmt.column_count())
+        .map(|_| ColumnProfile {
+            sketch: SparseSketch::new(budget.clone(), None),
+            nulls: 0,
+            integers: 0,
+            reals: 0,
+            texts: 0,
+            blobs: 0,
+        })
+        .collect::<Vec<_>>();
+    while stmt.step()? {
+        for (col, profile) in profiles.iter_mut().enumerate() {
+            let value = RawValue::new(stmt.value(col))?;
+            match value {
+                RawValue::Null => profile.nulls += 1,
+                RawValue::Int(_) => profile.integers += 1,
+                RawValue::Float(_) => profile.reals += 1,
+                RawValue::Text(_) => profile.texts += 1,
+                RawValue::Blob(_) => profile.blobs += 1,
+            }
+            add_value(&mut profile.sketch, value)?;
+        }
+    }
+    Ok(profiles
+        .iter()
+        .enumerate()
+        .map(|(col, profile)| {
+            let distinct = profile.sketch.cardinality();
+            vec![
+                Cell::Text(stmt.column_name(col)),
+                Cell::Float(distinct),
+                Cell::Float(distinct * STD_ERROR),
+                Cell::Int(profile.nulls),
+                Cell::Int(profile.integers),
+                Cell::Int(profile.reals),
+                Cell::Int(profile.texts),
+                Cell::Int(profile.blobs),
+            ]
+        })
+        .collect())
+}
+
+static PROFILE_TABLE: TableDef = TableDef {
+    name: "hyperminhash_profile",
+    schema: "CREATE TABLE x(name TEXT, distinct_count REAL, std_error REAL, null_count INTEGER, integer_count INTEGER, real_count INTEGER, text_count INTEGER, blob_count INTEGER, tbl HIDDEN)",
+    columns: 9,
+    args: 1,
+    required: 1,
+    rows: profile_rows,
+};
+
+/// One row per column: it's approximate distinct-count and the storage-classes seen
+#[no_mangle]
+pub extern "C" fn hyperminhash_profile_vtab() -> *const ffi::c_void {
+    &PROFILE_TABLE as *const TableDef as *const ffi::c_void
+}
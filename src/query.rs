@@ -0,0 +1,114 @@
+//! Running queries against the connection a function was called from
+use std::{convert::TryFrom, ffi, os::raw, ptr};
+
+use super::bindings::*;
+use super::{transient, HMHError};
+
+/// The most recent error-message of the connection
+pub(crate) unsafe fn errmsg(db: *mut sqlite3) -> String {
+    ffi::CStr::from_ptr(sqlite3_errmsg(db))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Quote an identifier for use in SQL
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quote a string for use as a literal in SQL
+pub(crate) fn quote_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Run a statement to completion, discarding any rows
+pub(crate) unsafe fn execute<'a>(db: *mut sqlite3, sql: &str) -> Result<(), HMHError<'a>> {
+    let mut stmt = Statement::prepare(db, sql)?;
+    while stmt.step()? {}
+    Ok(())
+}
+
+/// A prepared statement, finalized when dropped
+pub(crate) struct Statement {
+    db: *mut sqlite3,
+    stmt: *mut sqlite3_stmt,
+}
+
+impl Statement {
+    pub unsafe fn prepare<'a>(db: *mut sqlite3, sql: &str) -> Result<Self, HMHError<'a>> {
+        let sql = ffi::CString::new(sql).map_err(|e| HMHError::Query(e.to_string()))?;
+        let mut stmt = ptr::null_mut();
+        let rc = sqlite3_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut());
+        if rc != SQLITE_OK as raw::c_int {
+            return Err(HMHError::Query(errmsg(db)));
+        }
+        Ok(Self { db, stmt })
+    }
+
+    /// Advance to the next row, `false` once there are no more rows
+    pub unsafe fn step<'a>(&mut self) -> Result<bool, HMHError<'a>> {
+        match sqlite3_step(self.stmt) as u32 {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => Ok(false),
+            _ => Err(HMHError::Query(errmsg(self.db))),
+        }
+    }
+
+    /// Bind an integer to the parameter at the 1-based index `idx`
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub unsafe fn bind_int<'a>(&mut self, idx: usize, value: i64) -> Result<(), HMHError<'a>> {
+        let rc = sqlite3_bind_int64(self.stmt, idx as raw::c_int, value);
+        self.check(rc)
+    }
+
+    /// Bind a copy of `buf` to the parameter at the 1-based index `idx`
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub unsafe fn bind_blob<'a>(&mut self, idx: usize, buf: &[u8]) -> Result<(), HMHError<'a>> {
+        let len = raw::c_int::try_from(buf.len())
+            .map_err(|_| HMHError::Query("blob too large to bind".to_owned()))?;
+        let rc = sqlite3_bind_blob(
+            self.stmt,
+            idx as raw::c_int,
+            buf.as_ptr() as *const ffi::c_void,
+            len,
+            transient(),
+        );
+        self.check(rc)
+    }
+
+    /// Rewind the statement to be run again, keeping it's bindings
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub unsafe fn reset(&mut self) {
+        // Reports the error of the last step, which has been reported already
+        sqlite3_reset(self.stmt);
+    }
+
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    unsafe fn check<'a>(&self, rc: raw::c_int) -> Result<(), HMHError<'a>> {
+        if rc != SQLITE_OK as raw::c_int {
+            return Err(HMHError::Query(errmsg(self.db)));
+        }
+        Ok(())
+    }
+
+    pub unsafe fn column_count(&self) -> usize {
+        sqlite3_column_count(self.stmt) as usize
+    }
+
+    pub unsafe fn column_name(&self, col: usize) -> String {
+        ffi::CStr::from_ptr(sqlite3_column_name(self.stmt, col as raw::c_int))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The value of a column in the current row, valid until the next step
+    pub unsafe fn value(&self, col: usize) -> *mut sqlite3_value {
+        sqlite3_column_value(self.stmt, col as raw::c_int)
+    }
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.stmt) };
+    }
+}
@@ -1,25 +1,200 @@
-use std::{ffi, mem, os::raw, slice};
+use std::{borrow::Cow, convert::TryFrom, ffi, mem, os::raw, sync::Arc};
 
+use super::alloc::{Budget, Buf};
 use super::bindings::*;
-use super::{HMHError, RawValue, Sketch};
+use super::json::{add_elements, json_arg};
+use super::settings;
+use super::sparse::{SparseSketch, LZ_SHIFT, PRECISION, REGISTERS};
+use super::vtab::{Cell, Rows, TableDef};
+use super::{
+    add_row, aggregate_state_with, arguments, set_text_result, transient, ArgumentError, HMHError,
+    RawValue,
+};
 
-unsafe extern "C" fn drop_blob_buffer<T>(buf: *mut ffi::c_void) {
-    drop(Box::<T>::from_raw(buf as *mut _))
+mod compress;
+mod digest;
+mod inplace;
+mod integrity;
+mod keyed;
+mod text;
+mod timeline;
+mod upgrade;
+mod validate;
+mod view;
+
+use keyed::KeyId;
+use view::SketchView;
+
+/// Each register is serialized as a little-endian `u16`
+const SKETCH_SIZE: usize = REGISTERS * mem::size_of::<u16>();
+/// The registers, key-tag and trailer of a serialized sketch, at most
+const MAX_SKETCH_SIZE: usize = SKETCH_SIZE + keyed::TAG_SIZE + integrity::MAX_TRAILER;
+
+/// The registers of a serialized sketch
+fn registers(buf: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    buf.chunks_exact(2)
+        .map(|r| u16::from_le_bytes([r[0], r[1]]))
 }
 
-unsafe fn set_blob_result<T>(ctx: *mut sqlite3_context, value: Box<T>) {
-    let buf_len = std::mem::size_of_val(&*value);
-    let p = Box::into_raw(value) as *const ffi::c_void;
-    sqlite3_result_blob(ctx, p, buf_len as i32, Some(drop_blob_buffer::<T>));
+/// A serialized sketch has to be of exactly the right size
+fn check_size<'a>(buf: &[u8]) -> Result<(), HMHError<'a>> {
+    if buf.len() != SKETCH_SIZE {
+        let msg = if buf.starts_with(timeline::MAGIC) {
+            "value is a timeline, not a sketch".to_owned()
+        } else {
+            format!("expected {} bytes, found {}", SKETCH_SIZE, buf.len())
+        };
+        return Err(HMHError::Corrupt(msg));
+    }
+    Ok(())
 }
 
+/// The serialized sketch of a `BLOB`, borrowed unless compressed, or of any of
+/// it's text-encodings
+unsafe fn sketch_bytes<'a>(value: *mut sqlite3_value) -> Result<Cow<'a, [u8]>, HMHError<'a>> {
+    stored_bytes(value, MAX_SKETCH_SIZE)
+}
+
+/// As `sketch_bytes()`, inflating up to `limit` bytes
+unsafe fn stored_bytes<'a>(
+    value: *mut sqlite3_value,
+    limit: usize,
+) -> Result<Cow<'a, [u8]>, HMHError<'a>> {
+    let buf = match RawValue::new(value)? {
+        RawValue::Blob(b) => Cow::Borrowed(b),
+        RawValue::Text(s) => Cow::Owned(text::blob_from_text(s)?),
+        other => return Err(HMHError::ValueIsNotBlob(other)),
+    };
+    compress::decompress(buf, limit)
+}
+
+/// The registers and key-id of a serialized sketch, after checking it's trailer
+fn checked_registers<'a>(buf: &[u8]) -> Result<(&[u8], KeyId), HMHError<'a>> {
+    Ok(keyed::split(integrity::verify(None, buf)?))
+}
+
+/// Check that the serialized sketch or timeline, without it's trailer, loads
+fn check_format<'a>(buf: &[u8]) -> Result<(), HMHError<'a>> {
+    if buf.starts_with(timeline::MAGIC) {
+        timeline::TimelineView::checked(buf)?;
+    } else {
+        SketchView::new(keyed::split(buf).0)?;
+    }
+    Ok(())
+}
+
+/// A sketch's registers, copied to memory charged to a budget, and it's key-id
+type Loaded = (Buf<u8>, KeyId);
+
+/// Load a sketch's registers and it's key-id from a `BLOB` or any of it's
+/// text-encodings, as `checked_registers()` returns them
+unsafe fn sketch_from_value<'a>(
+    value: *mut sqlite3_value,
+    budget: Arc<Budget>,
+) -> Result<Loaded, HMHError<'a>> {
+    let buf = sketch_bytes(value)?;
+    let (registers, key_id) = checked_registers(&buf)?;
+    check_size(registers)?;
+    Ok((Buf::from_slice(registers, Some(budget))?, key_id))
+}
+
+/// A view of a serialized sketch's registers and it's key-id, as
+/// `checked_registers()` returns them
+fn sketch_view<'b, 'a>(buf: &'b [u8]) -> Result<(SketchView<'b>, KeyId), HMHError<'a>> {
+    let (registers, key_id) = checked_registers(buf)?;
+    Ok((SketchView::new(registers)?, key_id))
+}
+
+unsafe extern "C" fn drop_loaded(p: *mut ffi::c_void) {
+    drop(Box::<Loaded>::from_raw(p as *mut _))
+}
+
+/// The auxiliary data of an argument seen by a previous row, see `with_sketch_args()`
+static SEEN: u8 = 0;
+
+/// An argument of `with_sketch_args()`
+enum SketchArg<'a> {
+    /// The sketch of a constant argument, kept since an earlier row
+    Kept(&'a Loaded),
+    /// The sketch of a constant argument, to be kept from now on
+    Loaded(Box<Loaded>),
+    /// The serialized sketch of an argument that may change with every row
+    Read(Cow<'a, [u8]>),
+}
+
+/// Call `f` with the sketches of all arguments and the key-id they share. Sketches
+/// of constant arguments are kept as auxiliary data, so they are parsed once per
+/// statement instead of per row; their memory counts against the connection's
+/// budget.
+///
+/// sqlite only tells whether an argument is constant by keeping it's auxiliary
+/// data until the next row, so arguments are marked on their first row and only
+/// those still marked on the next get loaded and kept; all others are read in
+/// place, row by row.
+unsafe fn with_sketch_args<'a, T>(
+    ctx: *mut sqlite3_context,
+    args: &[*mut sqlite3_value],
+    f: impl FnOnce(&[SketchView], KeyId) -> T,
+) -> Result<T, HMHError<'a>> {
+    let mut sketch_args = Vec::with_capacity(args.len());
+    for (i, arg) in args.iter().enumerate() {
+        let aux = sqlite3_get_auxdata(ctx, i as raw::c_int);
+        sketch_args.push(if aux.is_null() {
+            SketchArg::Read(sketch_bytes(*arg).at(i)?)
+        } else if aux as *const u8 == &SEEN {
+            let budget = Budget::of_connection(ctx);
+            SketchArg::Loaded(Box::new(sketch_from_value(*arg, budget).at(i)?))
+        } else {
+            SketchArg::Kept(&*(aux as *const Loaded))
+        });
+    }
+    let mut views = Vec::with_capacity(args.len());
+    let mut key_ids = Vec::with_capacity(args.len());
+    for (i, arg) in sketch_args.iter().enumerate() {
+        let (view, key_id) = match arg {
+            SketchArg::Kept(loaded) => (SketchView::new(&loaded.0)?, loaded.1),
+            SketchArg::Loaded(loaded) => (SketchView::new(&loaded.0)?, loaded.1),
+            SketchArg::Read(buf) => sketch_view(buf).at(i)?,
+        };
+        views.push(view);
+        key_ids.push(key_id);
+    }
+    let r = f(&views, keyed::common(key_ids)?);
+    // sqlite discards the data once we return if the argument is not constant
+    for (i, arg) in sketch_args.into_iter().enumerate() {
+        let (p, destructor): (*mut ffi::c_void, Option<unsafe extern "C" fn(_)>) = match arg {
+            SketchArg::Kept(_) => continue,
+            SketchArg::Loaded(loaded) => (Box::into_raw(loaded) as *mut _, Some(drop_loaded)),
+            SketchArg::Read(_) => (&SEEN as *const u8 as *mut _, None),
+        };
+        sqlite3_set_auxdata(ctx, i as raw::c_int, p, destructor);
+    }
+    Ok(r)
+}
+
+unsafe fn set_blob_slice_result(ctx: *mut sqlite3_context, buf: &[u8]) {
+    match raw::c_int::try_from(buf.len()) {
+        Ok(len) => sqlite3_result_blob(ctx, buf.as_ptr() as *const ffi::c_void, len, transient()),
+        Err(_) => sqlite3_result_error_toobig(ctx),
+    }
+}
+
+/// The serialized sketch with it's key-tag, compressed if that pays off
+fn encode_sketch(sketch: &SparseSketch) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MAX_SKETCH_SIZE);
+    for reg in sketch.registers() {
+        buf.extend_from_slice(&reg.to_le_bytes());
+    }
+    buf.extend_from_slice(&keyed::tag(sketch.key_id));
+    compress::compress(&buf).unwrap_or(buf)
+}
+
+/// Return the sketch as a blob, encoded as `encode_sketch()` does
 unsafe fn sketch_to_result<'a>(
-    sk: &Sketch,
+    sk: &SparseSketch,
     ctx: &'a *mut sqlite3_context,
 ) -> Result<(), HMHError<'a>> {
-    let mut buf = Box::new([0; 32768]);
-    sk.save(&mut buf[..])?;
-    set_blob_result(*ctx, buf);
+    set_blob_slice_result(*ctx, &encode_sketch(sk));
     Ok(())
 }
 
@@ -29,15 +204,17 @@ pub unsafe extern "C" fn hyperminhash_zero(
     _num_values: raw::c_int,
     _values: *mut *mut sqlite3_value,
 ) {
-    HMHError::set_ctx(ctx, || sketch_to_result(&Sketch::default(), &ctx));
+    HMHError::set_ctx(ctx, || {
+        sketch_to_result(&SparseSketch::new(None, settings::hash_key(ctx)?), &ctx)
+    });
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn hyperminhash_serialize_final(ctx: *mut sqlite3_context) {
     HMHError::set_ctx(ctx, || {
-        let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut Sketch;
-        let sketch = if p.is_null() {
-            Box::new(Sketch::default())
+        let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut SparseSketch;
+        let sketch = if p.is_null() || (*p).is_null() {
+            Box::new(SparseSketch::new(None, settings::hash_key(ctx)?))
         } else {
             Box::from_raw(*p)
         };
@@ -51,10 +228,11 @@ pub unsafe extern "C" fn hyperminhash_deserialize(
     num_values: raw::c_int,
     values: *mut *mut sqlite3_value,
 ) {
-    assert!(num_values == 1); // Declared as such in shim.c
     HMHError::set_ctx(ctx, || {
-        let sk = Sketch::load(RawValue::new(*values)?.as_blob()?)?;
-        sqlite3_result_double(ctx, sk.cardinality());
+        let args = arguments(values, num_values, 1..=1)?;
+        let buf = sketch_bytes(args[0]).at(0)?;
+        let (view, _) = sketch_view(&buf).at(0)?;
+        sqlite3_result_double(ctx, view.cardinality());
         Ok(())
     });
 }
@@ -66,54 +244,84 @@ pub unsafe extern "C" fn hyperminhash_add(
     values: *mut *mut sqlite3_value,
 ) {
     HMHError::set_ctx(ctx, || {
-        let args = slice::from_raw_parts(values, num_values as usize);
-        let sum_sketch = args
-            .iter()
-            .map(|p| {
-                RawValue::new(*p)
-                    .and_then(|v| v.as_blob())
-                    .and_then(|b| Sketch::load(b).map_err(Into::into))
-            })
-            .fold(None, |sk1: Option<Result<_, HMHError>>, sk2| {
-                match (sk1, sk2) {
-                    (None, Ok(sk2)) => Some(Ok(sk2.clone())),
-                    (None, Err(e)) | (Some(Err(e)), _) | (Some(Ok(_)), Err(e)) => Some(Err(e)),
-                    (Some(Ok(mut sk1)), Ok(sk2)) => {
-                        sk1.union(&sk2);
-                        Some(Ok(sk1))
-                    }
-                }
-            })
-            .transpose()?
-            .unwrap_or_default();
-        sketch_to_result(&sum_sketch, &ctx)?;
-        Ok(())
+        let args = arguments(values, num_values, 0..=usize::MAX)?;
+        let sum_sketch = with_sketch_args(ctx, args, |sketches, key_id| {
+            let mut sum_sketch = SparseSketch::new(Some(Budget::of_connection(ctx)), None);
+            sum_sketch.key_id = key_id;
+            for sk in sketches {
+                sum_sketch.union(sk.registers())?;
+            }
+            Ok::<_, HMHError>(sum_sketch)
+        })??;
+        sketch_to_result(&sum_sketch, &ctx)
     });
 }
 
+/// Add a single row to a sketch; a NULL-sketch is taken to be empty
 #[no_mangle]
-pub unsafe extern "C" fn hyperminhash_union_step(
+pub unsafe extern "C" fn hyperminhash_insert(
     ctx: *mut sqlite3_context,
     num_values: raw::c_int,
     values: *mut *mut sqlite3_value,
 ) {
-    assert!(num_values == 1); // Declared as such in shim.c
     HMHError::set_ctx(ctx, || {
-        let sketch = Sketch::load(RawValue::new(*values)?.as_blob()?)?;
+        let (data, row) = arguments(values, num_values, 1..=usize::MAX)?
+            .split_first()
+            .unwrap();
+        let key = settings::hash_key(ctx)?;
+        let mut sketch = SparseSketch::new(Some(Budget::of_connection(ctx)), key);
+        if sqlite3_value_type(*data) as u32 != SQLITE_NULL {
+            let buf = sketch_bytes(*data).at(0)?;
+            let (view, key_id) = sketch_view(&buf).at(0)?;
+            keyed::check_rows(key, key_id).at(0)?;
+            sketch.union(view.registers())?;
+        }
+        add_row(&mut sketch, row)?;
+        sketch_to_result(&sketch, &ctx)
+    });
+}
 
-        let p = sqlite3_aggregate_context(ctx, mem::size_of::<*mut Sketch>() as raw::c_int)
-            as *mut *mut Sketch;
-        if p.is_null() {
-            sqlite3_result_error_nomem(ctx);
-            return Ok(());
+/// A sketch of a JSON-array's elements, as HYPERMINHASH_JSON() would count them
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_from_json(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let mut sketch =
+            SparseSketch::new(Some(Budget::of_connection(ctx)), settings::hash_key(ctx)?);
+        if let Some(json) = json_arg(args[0]).at(0)? {
+            add_elements(&mut sketch, &json)?;
         }
-        if (*p).is_null() {
-            *p = Box::into_raw(Box::new(sketch.clone()));
-        } else {
-            let running_sketch = &mut **p;
-            running_sketch.union(&sketch);
+        sketch_to_result(&sketch, &ctx)
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_union_step(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let buf = sketch_bytes(args[0]).at(0)?;
+        let (view, key_id) = sketch_view(&buf).at(0)?;
+        // Takes the key-id of the first sketch, instead of the connection's
+        let union = aggregate_state_with(ctx, || {
+            let mut union = SparseSketch::new(Some(Budget::of_connection(ctx)), None);
+            union.key_id = key_id;
+            Ok(union)
+        })?;
+        match union {
+            Some(union) => {
+                keyed::common([union.key_id, key_id]).at(0)?;
+                union.union(view.registers())
+            }
+            None => Ok(()),
         }
-        Ok(())
     })
 }
 
@@ -123,13 +331,142 @@ pub unsafe extern "C" fn hyperminhash_intersection(
     num_values: raw::c_int,
     values: *mut *mut sqlite3_value,
 ) {
-    assert!(num_values == 2); // Declared as such in shim.c
     HMHError::set_ctx(ctx, || {
-        let args = slice::from_raw_parts(values, num_values as usize);
-        let sketch1 = Sketch::load(RawValue::new(args[0])?.as_blob()?)?;
-        let sketch2 = Sketch::load(RawValue::new(args[1])?.as_blob()?)?;
+        let args = arguments(values, num_values, 2..=2)?;
+        let r = with_sketch_args(ctx, args, |sketches, _| {
+            sketches[0].intersection(sketches[1])
+        })?;
+        sqlite3_result_double(ctx, r);
+        Ok(())
+    });
+}
+
+/// Describe a serialized sketch; malformed data is reported, not raised
+fn sketch_info(buf: &[u8]) -> serde_json::Value {
+    let invalid = |error: String| {
+        serde_json::json!({
+            "valid": false,
+            "format": "raw",
+            "version": 1,
+            "size": buf.len(),
+            "error": error,
+        })
+    };
+    let size = buf.len();
+    let compression = Some("deflate").filter(|_| buf.starts_with(compress::MAGIC));
+    let buf = match compress::decompress(Cow::Borrowed(buf), timeline::MAX_SIZE) {
+        Ok(buf) => buf,
+        Err(e) => return invalid(e.to_string()),
+    };
+    if buf.starts_with(timeline::MAGIC) {
+        let integrity = integrity::split(&buf).1.name();
+        let loaded = integrity::verify(None, &buf).and_then(timeline::TimelineView::checked);
+        return match loaded {
+            Ok(t) => serde_json::json!({
+                "valid": true,
+                "format": "timeline",
+                "version": timeline::VERSION,
+                "size": size,
+                "precision": PRECISION,
+                "compression": compression,
+                "integrity": integrity,
+                "key_id": keyed::format_id(t.key_id),
+                "bucket_width": t.width,
+                "buckets": t.buckets().count(),
+                "first_bucket": t.buckets().next().map(|b| b.start),
+                "last_bucket": t.buckets().next_back().map(|b| b.start),
+            }),
+            Err(e) => serde_json::json!({
+                "valid": false,
+                "format": "timeline",
+                "size": size,
+                "error": e.to_string(),
+            }),
+        };
+    }
+    let integrity = integrity::split(&buf).1.name();
+    let loaded = integrity::verify(None, &buf)
+        .map(keyed::split)
+        .and_then(|(registers, key_id)| Ok((SketchView::new(registers)?, key_id)));
+    let (sketch, key_id) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => return invalid(e.to_string()),
+    };
+    let mut histogram = [0usize; 1 << (16 - LZ_SHIFT)];
+    for reg in registers(&buf[..SKETCH_SIZE]) {
+        histogram[(reg >> LZ_SHIFT) as usize] += 1;
+    }
+    serde_json::json!({
+        "valid": true,
+        "format": "raw",
+        "version": 1,
+        "size": size,
+        "precision": PRECISION,
+        "registers": REGISTERS,
+        "compression": compression,
+        "integrity": integrity,
+        "key_id": keyed::format_id(key_id),
+        "empty_registers": histogram[0],
+        "histogram": &histogram[..],
+        "cardinality": sketch.cardinality(),
+    })
+}
 
-        sqlite3_result_double(ctx, sketch1.intersection(&sketch2));
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_info(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let info = match RawValue::new(args[0])? {
+            RawValue::Blob(b) => sketch_info(b),
+            RawValue::Text(s) => match text::blob_from_text(s) {
+                Ok(b) => sketch_info(&b),
+                Err(e) => serde_json::json!({
+                    "valid": false,
+                    "format": "text",
+                    "error": e.to_string(),
+                }),
+            },
+            other => return Err(HMHError::ValueIsNotBlob(other).at(0)),
+        };
+        set_text_result(ctx, &info.to_string());
         Ok(())
     });
 }
+
+unsafe fn register_rows<'a>(
+    _db: *mut sqlite3,
+    args: &'a [Option<*mut sqlite3_value>],
+) -> Result<Rows, HMHError<'a>> {
+    let buf = sketch_bytes(args[0].ok_or(HMHError::TooFewArguments(1))?).at(0)?;
+    let (view, _) = sketch_view(&buf).at(0)?;
+    Ok(view
+        .registers()
+        .enumerate()
+        .map(|(idx, reg)| {
+            vec![
+                Cell::Int(idx as i64),
+                Cell::Int(i64::from(reg >> LZ_SHIFT)),
+                Cell::Int(i64::from(reg & ((1 << LZ_SHIFT) - 1))),
+            ]
+        })
+        .collect())
+}
+
+static REGISTERS_TABLE: TableDef = TableDef {
+    name: "hyperminhash_registers",
+    schema: "CREATE TABLE x(idx INTEGER, lz INTEGER, minhash INTEGER, sketch HIDDEN)",
+    columns: 4,
+    args: 1,
+    required: 1,
+    rows: register_rows,
+};
+
+/// One row per register: it's index, leading-zero count and MinHash-bits
+#[no_mangle]
+pub extern "C" fn hyperminhash_registers_vtab() -> *const ffi::c_void {
+    &REGISTERS_TABLE as *const TableDef as *const ffi::c_void
+}
@@ -0,0 +1,51 @@
+//! Deflate-compressed sketches
+//!
+//! The registers of a sketch of few elements are mostly empty, those of a sketch
+//! of some thousand elements mostly share their leading-zero counts. Unless built
+//! without the `compress`-feature, our results are deflated, including their
+//! trailer, and flagged by a leading `HMHZ`; if compressing does not make the blob
+//! smaller, it is kept as is. Timelines are compressed the same way. Compressed
+//! blobs are inflated by all loaders before looking at them, with or without the
+//! feature.
+use std::borrow::Cow;
+
+use super::super::HMHError;
+use super::timeline;
+
+pub(super) const MAGIC: &[u8; 4] = b"HMHZ";
+
+/// The compressed form of a serialized sketch or timeline, if it is smaller
+#[cfg(feature = "compress")]
+pub(super) fn compress(buf: &[u8]) -> Option<Vec<u8>> {
+    let mut compressed = MAGIC.to_vec();
+    compressed.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(buf, 6));
+    Some(compressed).filter(|c| c.len() < buf.len())
+}
+
+#[cfg(not(feature = "compress"))]
+pub(super) fn compress(_buf: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Inflate a compressed serialized sketch or timeline, leaving anything else as
+/// is. Anything inflating to more than `limit` bytes is not one of ours.
+pub(super) fn decompress<'b, 'a>(
+    buf: Cow<'b, [u8]>,
+    limit: usize,
+) -> Result<Cow<'b, [u8]>, HMHError<'a>> {
+    match buf.strip_prefix(MAGIC) {
+        Some(deflated) => inflate(deflated, limit).map(Cow::Owned),
+        None => Ok(buf),
+    }
+}
+
+fn inflate<'a>(deflated: &[u8], limit: usize) -> Result<Vec<u8>, HMHError<'a>> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(deflated, limit).map_err(|e| {
+        // A timeline inflates to more than a sketch may
+        if limit < timeline::MAX_SIZE && e.output.starts_with(timeline::MAGIC) {
+            HMHError::Corrupt("value is a timeline, not a sketch".to_owned())
+        } else {
+            HMHError::Corrupt(format!("can't inflate compressed sketch: {}", e))
+        }
+    })
+}
@@ -0,0 +1,29 @@
+//! CRC-32 and HMAC-SHA256, for the trailers of serialized sketches
+//!
+//! The CRC is the common one of zlib and PNG, as computed by `crc32fast`; the
+//! signatures are those of the `hmac` and `sha2` crates.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The CRC-32 (IEEE 802.3) of `buf`
+pub(super) fn crc32(buf: &[u8]) -> u32 {
+    crc32fast::hash(buf)
+}
+
+fn mac(key: &[u8], msg: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key");
+    mac.update(msg);
+    mac
+}
+
+/// The HMAC-SHA256 of `msg` under `key`
+pub(super) fn hmac_sha256(key: &[u8], msg: &[u8]) -> [u8; 32] {
+    mac(key, msg).finalize().into_bytes().into()
+}
+
+/// Whether `tag` is the HMAC-SHA256 of `msg` under `key`, compared in constant time
+pub(super) fn verify_hmac_sha256(key: &[u8], msg: &[u8], tag: &[u8]) -> bool {
+    mac(key, msg).verify_slice(tag).is_ok()
+}
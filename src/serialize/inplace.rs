@@ -0,0 +1,192 @@
+//! Updating stored sketches in place, using incremental blob-IO
+//!
+//! Adding a row to a sketch changes at most one register. Instead of reading and
+//! writing the whole blob, we compute that register exactly as `Sketch::add` does
+//! and only touch it's two bytes in the serialized layout. Keyed sketches are
+//! updated in place as well, their key-tag follows the registers. Compressed
+//! sketches are rewritten uncompressed on their first update. Checked and signed
+//! sketches are not updated at all. Rows are hashed with the connection's hash-key
+//! if the sketch is keyed, and with the default seed otherwise.
+use std::{borrow::Cow, ffi, os::raw, ptr};
+
+use super::super::bindings::*;
+use super::super::query::{errmsg, quote_ident, Statement};
+use super::super::settings;
+use super::super::sparse::{hash, register_update};
+use super::super::{arguments, HMHError, RawValue, Row};
+use super::{compress, keyed};
+use super::{MAX_SKETCH_SIZE, SKETCH_SIZE};
+
+/// Closes the blob-handle when dropped
+struct Blob(*mut sqlite3_blob);
+
+impl Drop for Blob {
+    fn drop(&mut self) {
+        unsafe { sqlite3_blob_close(self.0) };
+    }
+}
+
+impl Blob {
+    unsafe fn open<'a>(
+        db: *mut sqlite3,
+        schema: &str,
+        table: &str,
+        column: &str,
+        rowid: i64,
+    ) -> Result<Self, HMHError<'a>> {
+        let schema = ffi::CString::new(schema)
+            .map_err(|_| HMHError::InvalidArgument("schema-name contains NUL"))?;
+        let table = ffi::CString::new(table)
+            .map_err(|_| HMHError::InvalidArgument("table-name contains NUL"))?;
+        let column = ffi::CString::new(column)
+            .map_err(|_| HMHError::InvalidArgument("column-name contains NUL"))?;
+        let mut blob = ptr::null_mut();
+        let rc = sqlite3_blob_open(
+            db,
+            schema.as_ptr(),
+            table.as_ptr(),
+            column.as_ptr(),
+            rowid,
+            1, // read-write
+            &mut blob,
+        );
+        let blob = Blob(blob);
+        if rc != SQLITE_OK as raw::c_int {
+            return Err(HMHError::BlobIo(errmsg(db)));
+        }
+        Ok(blob)
+    }
+
+    unsafe fn read<'a>(
+        &self,
+        db: *mut sqlite3,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<(), HMHError<'a>> {
+        if sqlite3_blob_read(
+            self.0,
+            buf.as_mut_ptr() as *mut ffi::c_void,
+            buf.len() as raw::c_int,
+            offset as raw::c_int,
+        ) != SQLITE_OK as raw::c_int
+        {
+            return Err(HMHError::BlobIo(errmsg(db)));
+        }
+        Ok(())
+    }
+}
+
+/// Add a single row to the sketch stored at (schema, table, column, rowid), writing
+/// at most a single register. Returns 1 if the sketch changed, 0 otherwise.
+///
+/// The schema-name is always the first argument; NULL stands for `main`.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_insert_inplace(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 4..=usize::MAX)?;
+        let schema = match RawValue::new(args[0])? {
+            RawValue::Null => "main",
+            RawValue::Text(s) => s,
+            other => return Err(HMHError::UnexpectedType("a schema-name", other).at(0)),
+        };
+        let table = match RawValue::new(args[1])? {
+            RawValue::Text(s) => s,
+            other => return Err(HMHError::UnexpectedType("a table-name", other).at(1)),
+        };
+        let column = match RawValue::new(args[2])? {
+            RawValue::Text(s) => s,
+            other => return Err(HMHError::UnexpectedType("a column-name", other).at(2)),
+        };
+        let rowid = match RawValue::new(args[3])? {
+            RawValue::Int(i) => i,
+            other => return Err(HMHError::UnexpectedType("a rowid", other).at(3)),
+        };
+
+        let db = sqlite3_context_db_handle(ctx);
+        let blob = Blob::open(db, schema, table, column, rowid)?;
+        let size = sqlite3_blob_bytes(blob.0) as usize;
+        let mut magic = [0u8; 4];
+        if size >= magic.len() {
+            blob.read(db, &mut magic, 0)?;
+        }
+        // Compressed sketches are inflated here and written back as a whole, but
+        // uncompressed, so they can be updated in place from then on
+        let mut inflated = None;
+        if &magic == compress::MAGIC {
+            let mut buf = vec![0; size];
+            blob.read(db, &mut buf, 0)?;
+            let buf = compress::decompress(Cow::Owned(buf), MAX_SKETCH_SIZE)?;
+            inflated = Some(buf.into_owned());
+        }
+        let size = inflated.as_ref().map_or(size, |buf| buf.len());
+        let read = |buf: &mut [u8], offset: usize| match &inflated {
+            Some(inflated) => {
+                buf.copy_from_slice(&inflated[offset..offset + buf.len()]);
+                Ok(())
+            }
+            None => blob.read(db, buf, offset),
+        };
+        let is_keyed = size == SKETCH_SIZE + keyed::TAG_SIZE;
+        // A checksum or signature would no longer match after the update
+        if size > SKETCH_SIZE && size <= MAX_SKETCH_SIZE && !is_keyed {
+            return Err(HMHError::Integrity(
+                "sketches with a checksum or signature can't be updated in place",
+            ));
+        }
+        if size != SKETCH_SIZE && !is_keyed {
+            return Err(HMHError::Corrupt(format!(
+                "expected a serialized sketch of {} bytes, found {}",
+                SKETCH_SIZE, size
+            )));
+        }
+        let key_id = if is_keyed {
+            let mut tag = [0u8; keyed::TAG_SIZE];
+            read(&mut tag, SKETCH_SIZE)?;
+            let id = keyed::parse_tag(&tag)
+                .ok_or_else(|| HMHError::Corrupt("malformed key-tag".to_owned()))?;
+            Some(id)
+        } else {
+            None
+        };
+        let key = settings::get(ctx).hash_key.filter(|_| key_id.is_some());
+        keyed::check_rows(key, key_id)?;
+
+        let row = Row::new(&args[4..])?;
+        let (idx, reg) = register_update(hash(row, key.map_or(0, |k| k.seed)));
+        let offset = (idx * 2) as raw::c_int;
+        let mut current = [0u8; 2];
+        read(&mut current, idx * 2)?;
+        let changed = u16::from_le_bytes(current) < reg;
+        if let (true, Some(mut buf)) = (changed, inflated) {
+            buf[idx * 2..idx * 2 + 2].copy_from_slice(&reg.to_le_bytes());
+            drop(blob);
+            let mut stmt = Statement::prepare(
+                db,
+                &format!(
+                    "UPDATE {}.{} SET {} = ?1 WHERE rowid = ?2",
+                    quote_ident(schema),
+                    quote_ident(table),
+                    quote_ident(column)
+                ),
+            )?;
+            stmt.bind_blob(1, &buf)?;
+            stmt.bind_int(2, rowid)?;
+            stmt.step()?;
+        } else if changed
+            && sqlite3_blob_write(
+                blob.0,
+                reg.to_le_bytes().as_ptr() as *const ffi::c_void,
+                2,
+                offset,
+            ) != SQLITE_OK as raw::c_int
+        {
+            return Err(HMHError::BlobIo(errmsg(db)));
+        }
+        sqlite3_result_int64(ctx, changed as i64);
+        Ok(())
+    })
+}
@@ -0,0 +1,221 @@
+//! Checksums and signatures of serialized sketches
+//!
+//! A serialized sketch may be followed by a trailer: `HMHC` and the CRC-32 of the
+//! registers, or `HMHS` and their HMAC-SHA256; a key-tag, see `keyed`, counts as
+//! part of the registers here. HYPERMINHASH_CHECKSUM() and HYPERMINHASH_SIGN() add
+//! them, other functions' results carry none. Every loader checks a checksum and
+//! strips the trailer before looking at the registers; signatures are only checked
+//! by HYPERMINHASH_VERIFY(), against the connection's key, which keeps all other
+//! functions independent of it. Timelines carry the same trailer, over all of their
+//! buckets.
+use std::{mem, os::raw, sync::Arc};
+
+use super::super::bindings::*;
+use super::super::settings;
+use super::super::{arguments, ArgumentError, HMHError, RawValue};
+use super::digest::{crc32, hmac_sha256, verify_hmac_sha256};
+use super::SKETCH_SIZE;
+use super::{check_format, compress, keyed, set_blob_slice_result, stored_bytes, timeline};
+
+const CRC_MAGIC: &[u8; 4] = b"HMHC";
+const HMAC_MAGIC: &[u8; 4] = b"HMHS";
+const CRC_TRAILER: usize = CRC_MAGIC.len() + 4;
+const HMAC_TRAILER: usize = HMAC_MAGIC.len() + 32;
+
+/// The largest trailer a serialized sketch may have
+pub(super) const MAX_TRAILER: usize = HMAC_TRAILER;
+
+pub(super) enum Trailer<'b> {
+    None,
+    Crc(u32),
+    Hmac(&'b [u8]),
+}
+
+impl<'b> Trailer<'b> {
+    pub(super) fn name(&self) -> Option<&'static str> {
+        match self {
+            Trailer::None => None,
+            Trailer::Crc(_) => Some("crc32"),
+            Trailer::Hmac(_) => Some("hmac-sha256"),
+        }
+    }
+}
+
+/// Split a serialized sketch or timeline into it's registers and trailer. Anything
+/// not recognized as a trailer is left in place, for `check_size()` to complain about.
+pub(super) fn split(buf: &[u8]) -> (&[u8], Trailer<'_>) {
+    let is_timeline = buf.starts_with(timeline::MAGIC);
+    let at = |len: usize| {
+        buf.len().checked_sub(len).filter(|at| {
+            if is_timeline {
+                timeline::is_size(buf, *at)
+            } else {
+                *at == SKETCH_SIZE || *at == SKETCH_SIZE + keyed::TAG_SIZE
+            }
+        })
+    };
+    if let Some(at) = at(CRC_TRAILER).filter(|at| buf[*at..].starts_with(CRC_MAGIC)) {
+        let (registers, trailer) = buf.split_at(at);
+        let crc = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        return (registers, Trailer::Crc(crc));
+    }
+    if let Some(at) = at(HMAC_TRAILER).filter(|at| buf[*at..].starts_with(HMAC_MAGIC)) {
+        let (registers, trailer) = buf.split_at(at);
+        return (registers, Trailer::Hmac(&trailer[HMAC_MAGIC.len()..]));
+    }
+    (buf, Trailer::None)
+}
+
+/// The registers followed by their checksum
+fn with_crc(registers: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(registers.len() + CRC_TRAILER);
+    buf.extend_from_slice(registers);
+    buf.extend_from_slice(CRC_MAGIC);
+    buf.extend_from_slice(&crc32(registers).to_le_bytes());
+    buf
+}
+
+/// The registers followed by their signature
+fn with_hmac(key: &[u8], registers: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(registers.len() + HMAC_TRAILER);
+    buf.extend_from_slice(registers);
+    buf.extend_from_slice(HMAC_MAGIC);
+    buf.extend_from_slice(&hmac_sha256(key, registers));
+    buf
+}
+
+pub(super) fn check_crc<'a>(registers: &[u8], crc: u32) -> Result<(), HMHError<'a>> {
+    if crc32(registers) != crc {
+        return Err(HMHError::Integrity("checksum does not match"));
+    }
+    Ok(())
+}
+
+/// Check the serialized sketch's or timeline's trailer, returning what it covers.
+/// A signature is only checked given a key, which the sketch then has to be signed
+/// with; without one, it is stripped unchecked.
+pub(super) fn verify<'a, 'b>(key: Option<&[u8]>, buf: &'b [u8]) -> Result<&'b [u8], HMHError<'a>> {
+    let (registers, trailer) = split(buf);
+    match (trailer, key) {
+        (Trailer::Hmac(mac), Some(key)) => {
+            if !verify_hmac_sha256(key, registers, mac) {
+                return Err(HMHError::Integrity("signature does not match"));
+            }
+        }
+        (_, Some(_)) => return Err(HMHError::Integrity("sketch is not signed")),
+        (Trailer::Crc(crc), None) => check_crc(registers, crc)?,
+        (Trailer::Hmac(_), None) | (Trailer::None, None) => {}
+    }
+    Ok(registers)
+}
+
+/// The serialized sketch or timeline signed with the key instead of the trailer it
+/// had; a signature it carries already has to be the key's
+pub(super) fn sign<'a>(key: &[u8], buf: &[u8]) -> Result<Vec<u8>, HMHError<'a>> {
+    let registers = match split(buf).1 {
+        Trailer::Hmac(_) => verify(Some(key), buf),
+        _ => verify(None, buf),
+    }?;
+    Ok(with_hmac(key, registers))
+}
+
+/// The connection's HMAC-key, for the functions which require one
+pub(super) unsafe fn hmac_key<'a>(ctx: *mut sqlite3_context) -> Result<Arc<[u8]>, HMHError<'a>> {
+    settings::get(ctx)
+        .hmac_key
+        .clone()
+        .ok_or(HMHError::Integrity("no HMAC-key is set"))
+}
+
+/// HYPERMINHASH_CHECKSUM(sketch), the sketch or timeline with a checksum instead of
+/// the trailer it had, if any; a signature is dropped unchecked
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_checksum(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        if sqlite3_value_type(args[0]) as u32 == SQLITE_NULL {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        let buf = stored_bytes(args[0], timeline::MAX_SIZE).at(0)?;
+        let registers = verify(None, &buf).at(0)?;
+        check_format(registers).at(0)?;
+        let buf = with_crc(registers);
+        set_blob_slice_result(ctx, &compress::compress(&buf).unwrap_or(buf));
+        Ok(())
+    })
+}
+
+/// HYPERMINHASH_SIGN(sketch), the sketch or timeline signed with the connection's
+/// HMAC-key instead of the trailer it had, if any; a signature has to match
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_sign(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        if sqlite3_value_type(args[0]) as u32 == SQLITE_NULL {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        let key = hmac_key(ctx)?;
+        let buf = stored_bytes(args[0], timeline::MAX_SIZE).at(0)?;
+        check_format(verify(None, &buf).at(0)?).at(0)?;
+        let buf = sign(&key, &buf).at(0)?;
+        set_blob_slice_result(ctx, &compress::compress(&buf).unwrap_or(buf));
+        Ok(())
+    })
+}
+
+/// HYPERMINHASH_VERIFY(sketch), the sketch or timeline as given if it is signed with
+/// the connection's HMAC-key; fails otherwise
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_verify(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        if sqlite3_value_type(args[0]) as u32 == SQLITE_NULL {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        let key = hmac_key(ctx)?;
+        let buf = stored_bytes(args[0], timeline::MAX_SIZE).at(0)?;
+        check_format(verify(Some(&key), &buf).at(0)?).at(0)?;
+        sqlite3_result_value(ctx, args[0]);
+        Ok(())
+    })
+}
+
+/// Set the key HYPERMINHASH_SIGN() and HYPERMINHASH_VERIFY() use, or clear it with
+/// NULL. Returns whether a key was set before.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_hmac_key(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let key: Option<Arc<[u8]>> = match RawValue::new(args[0])? {
+            RawValue::Null => None,
+            RawValue::Blob(b) if !b.is_empty() => Some(b.into()),
+            RawValue::Text(s) if !s.is_empty() => Some(s.as_bytes().into()),
+            RawValue::Blob(_) | RawValue::Text(_) => {
+                return Err(HMHError::InvalidArgument("the key is empty").at(0))
+            }
+            other => return Err(HMHError::UnexpectedType("a BLOB, TEXT or NULL", other).at(0)),
+        };
+        let previous = settings::update(ctx, |s| mem::replace(&mut s.hmac_key, key));
+        sqlite3_result_int64(ctx, previous.is_some() as i64);
+        Ok(())
+    })
+}
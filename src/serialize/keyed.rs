@@ -0,0 +1,133 @@
+//! Hashing rows under a secret
+//!
+//! Anyone holding a sketch can tell whether a value is likely counted in it, by
+//! adding the value and checking whether a register changes. The keyed functions,
+//! like HYPERMINHASH_KEYED_SERIALIZE(), hash rows with a seed derived from the
+//! connection's hash-key instead, which makes such a probe meaningless without the
+//! key. Sketches of keyed rows are tagged with `HMHK` and the key's id after their
+//! registers, ahead of any trailer; the id is derived from the key independently
+//! of the seed and reveals neither.
+//!
+//! Sketches of different keys count the same value at different registers, so
+//! sketches are only ever combined with sketches of the same key, and rows are only
+//! added to sketches of the connection's key. Counting, merging and intersecting
+//! keyed sketches does not require the key.
+use std::{convert::TryFrom, mem, os::raw};
+
+use super::super::bindings::*;
+use super::super::settings::{self, HashKey};
+use super::super::{arguments, HMHError, RawValue};
+use super::digest::hmac_sha256;
+use super::SKETCH_SIZE;
+
+const MAGIC: &[u8; 4] = b"HMHK";
+/// The size of the key-tag following the registers of a keyed sketch
+pub(super) const TAG_SIZE: usize = MAGIC.len() + 8;
+
+/// The id of the key a sketch was hashed with; `None` if it was not keyed
+pub(super) type KeyId = Option<u64>;
+
+/// Split a serialized sketch into it's registers and key-id. Anything not
+/// recognized as a key-tag is left in place, for `check_size()` to complain about.
+pub(super) fn split(buf: &[u8]) -> (&[u8], KeyId) {
+    if buf.len() == SKETCH_SIZE + TAG_SIZE {
+        let (registers, tag) = buf.split_at(SKETCH_SIZE);
+        if let Some(id) = parse_tag(tag) {
+            return (registers, Some(id));
+        }
+    }
+    (buf, None)
+}
+
+/// The key-id of a key-tag
+pub(super) fn parse_tag(tag: &[u8]) -> Option<u64> {
+    let id = <[u8; 8]>::try_from(tag.strip_prefix(MAGIC)?).ok()?;
+    Some(u64::from_le_bytes(id))
+}
+
+/// The key-tag to append to the registers; empty for sketches that are not keyed
+pub(super) fn tag(key_id: KeyId) -> Vec<u8> {
+    let mut tag = Vec::with_capacity(TAG_SIZE);
+    if let Some(id) = key_id {
+        tag.extend_from_slice(MAGIC);
+        tag.extend_from_slice(&id.to_le_bytes());
+    }
+    tag
+}
+
+/// The key-id as shown to users
+pub(super) fn format_id(key_id: KeyId) -> Option<String> {
+    key_id.map(|id| format!("{:016x}", id))
+}
+
+pub(super) fn parse_id(id: &str) -> Option<u64> {
+    Some(id)
+        .filter(|id| id.len() == 16)
+        .and_then(|id| u64::from_str_radix(id, 16).ok())
+}
+
+/// The key-id shared by all sketches being combined
+pub(super) fn common<'a>(key_ids: impl IntoIterator<Item = KeyId>) -> Result<KeyId, HMHError<'a>> {
+    let mut key_ids = key_ids.into_iter();
+    let first = key_ids.next().flatten();
+    if key_ids.any(|id| id != first) {
+        return Err(HMHError::HashKey(
+            "sketches were hashed with different keys",
+        ));
+    }
+    Ok(first)
+}
+
+/// Check that rows hashed with the function's key may be added to the sketch
+pub(super) fn check_rows<'a>(key: Option<HashKey>, key_id: KeyId) -> Result<(), HMHError<'a>> {
+    match (key, key_id) {
+        (Some(key), Some(id)) if key.id != id => {
+            Err(HMHError::HashKey("sketch was hashed with a different key"))
+        }
+        (None, Some(_)) => Err(HMHError::HashKey(
+            "sketch is keyed, rows have to be hashed with it's key",
+        )),
+        (Some(_), None) => Err(HMHError::HashKey(
+            "sketch is not keyed, rows have to be hashed without a key",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The seed and id derived from a secret
+fn derive(secret: &[u8]) -> HashKey {
+    let word = |label: &[u8]| {
+        let mut w = [0; 8];
+        w.copy_from_slice(&hmac_sha256(secret, label)[..8]);
+        u64::from_le_bytes(w)
+    };
+    HashKey {
+        seed: word(b"hyperminhash hash-seed"),
+        id: word(b"hyperminhash key-id"),
+    }
+}
+
+/// Set the secret the keyed functions hash rows with, or clear it with NULL. Returns
+/// whether a key was set before.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_hash_key(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let key = match RawValue::new(args[0])? {
+            RawValue::Null => None,
+            RawValue::Blob(b) if !b.is_empty() => Some(derive(b)),
+            RawValue::Text(s) if !s.is_empty() => Some(derive(s.as_bytes())),
+            RawValue::Blob(_) | RawValue::Text(_) => {
+                return Err(HMHError::InvalidArgument("the key is empty").at(0))
+            }
+            other => return Err(HMHError::UnexpectedType("a BLOB, TEXT or NULL", other).at(0)),
+        };
+        let previous = settings::update(ctx, |s| mem::replace(&mut s.hash_key, key));
+        sqlite3_result_int64(ctx, previous.is_some() as i64);
+        Ok(())
+    })
+}
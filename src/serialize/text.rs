@@ -0,0 +1,234 @@
+//! Text-encodings for sketches that need to travel through systems which can't carry binary data
+use std::{collections::BTreeMap, convert::TryFrom, os::raw};
+
+use super::super::bindings::*;
+use super::super::columns::{columns_object, json_object, take_columns};
+use super::super::grouped::take_groups;
+use super::super::sparse::SparseSketch;
+use super::super::{arguments, ArgumentError, HMHError, RawValue};
+use super::keyed::{self, KeyId};
+use super::timeline::{self, TimelineView};
+use super::{
+    check_format, compress, encode_sketch, integrity, registers, set_blob_slice_result,
+    set_text_result, stored_bytes, SketchView, PRECISION, REGISTERS, SKETCH_SIZE,
+};
+
+/// Decode a sketch's text-encoding into it's serialized form
+///
+/// The JSON-encoding is recognized by it's leading `{`, everything else is taken
+/// to be base64.
+pub(super) fn blob_from_text<'a>(text: &str) -> Result<Vec<u8>, HMHError<'a>> {
+    let text = text.trim();
+    if text.starts_with('{') {
+        blob_from_json(text)
+    } else {
+        base64::decode(text).map_err(|e| HMHError::InvalidText(e.to_string()))
+    }
+}
+
+fn invalid<'a>(e: &str) -> HMHError<'a> {
+    HMHError::InvalidText(e.to_owned())
+}
+
+/// The JSON-encoding of a timeline is recognized by it's `bucket_width`
+fn blob_from_json<'a>(text: &str) -> Result<Vec<u8>, HMHError<'a>> {
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|e| HMHError::InvalidText(e.to_string()))?;
+    if value["precision"] != PRECISION {
+        return Err(invalid("unsupported precision"));
+    }
+    if !value["bucket_width"].is_null() {
+        return timeline_from_json(&value);
+    }
+    let mut buf = Vec::with_capacity(SKETCH_SIZE + keyed::TAG_SIZE);
+    for reg in registers_from_json(&value["registers"])? {
+        buf.extend_from_slice(&reg.to_le_bytes());
+    }
+    buf.extend_from_slice(&keyed::tag(key_id_from_json(&value)?));
+    Ok(buf)
+}
+
+fn timeline_from_json<'a>(value: &serde_json::Value) -> Result<Vec<u8>, HMHError<'a>> {
+    let width = value["bucket_width"]
+        .as_i64()
+        .ok_or_else(|| invalid("`bucket_width` is not an integer"))?;
+    let buckets = value["buckets"]
+        .as_object()
+        .ok_or_else(|| invalid("`buckets` is not an object"))?;
+    let mut ordered = BTreeMap::new();
+    for (start, regs) in buckets {
+        let start = start
+            .parse::<i64>()
+            .map_err(|_| invalid("bucket-start is not an integer"))?;
+        if ordered.insert(start, regs).is_some() {
+            return Err(invalid("duplicate bucket-start"));
+        }
+    }
+    let mut buf = timeline::encode_header(width, key_id_from_json(value)?);
+    for (start, regs) in ordered {
+        let regs = registers_from_json(regs)?;
+        timeline::encode_bucket(&mut buf, start, || regs.iter().copied());
+    }
+    Ok(buf)
+}
+
+/// All registers, from an object of the non-empty ones by index
+fn registers_from_json<'a>(regs: &serde_json::Value) -> Result<Vec<u16>, HMHError<'a>> {
+    let regs = regs
+        .as_object()
+        .ok_or_else(|| invalid("`registers` is not an object"))?;
+    let mut registers = vec![0; REGISTERS];
+    for (idx, reg) in regs {
+        let idx = idx
+            .parse::<usize>()
+            .ok()
+            .filter(|idx| *idx < REGISTERS)
+            .ok_or_else(|| invalid("register-index out of range"))?;
+        registers[idx] = reg
+            .as_u64()
+            .and_then(|r| u16::try_from(r).ok())
+            .ok_or_else(|| invalid("register-value out of range"))?;
+    }
+    Ok(registers)
+}
+
+fn key_id_from_json<'a>(value: &serde_json::Value) -> Result<KeyId, HMHError<'a>> {
+    match &value["key_id"] {
+        serde_json::Value::Null => Ok(None),
+        id => Ok(Some(
+            id.as_str()
+                .and_then(keyed::parse_id)
+                .ok_or_else(|| invalid("`key_id` is not a key-id"))?,
+        )),
+    }
+}
+
+/// The non-empty registers, by index
+fn register_map(registers: impl Iterator<Item = u16>) -> serde_json::Value {
+    registers
+        .enumerate()
+        .filter(|(_, reg)| *reg != 0)
+        .map(|(idx, reg)| (idx.to_string(), reg.into()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The JSON-encoding lists only non-empty registers, by index, and the key-id of
+/// keyed sketches
+fn json_from_blob(buf: &[u8], key_id: KeyId) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "precision": PRECISION,
+        "registers": register_map(registers(buf)),
+    });
+    if let Some(id) = keyed::format_id(key_id) {
+        json["key_id"] = id.into();
+    }
+    json
+}
+
+/// The JSON-encoding of a timeline lists the non-empty registers of each bucket,
+/// by the bucket's start
+fn json_from_timeline(view: &TimelineView) -> serde_json::Value {
+    let buckets = view
+        .buckets()
+        .map(|b| (b.start.to_string(), register_map(b.registers())))
+        .collect::<serde_json::Map<_, _>>();
+    let mut json = serde_json::json!({
+        "precision": PRECISION,
+        "bucket_width": view.width,
+        "buckets": buckets,
+    });
+    if let Some(id) = keyed::format_id(view.key_id) {
+        json["key_id"] = id.into();
+    }
+    json
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_to_text(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=2)?;
+        let buf = stored_bytes(args[0], timeline::MAX_SIZE).at(0)?;
+        let body = integrity::verify(None, &buf).at(0)?;
+        let timeline = if body.starts_with(timeline::MAGIC) {
+            Some(TimelineView::checked(body).at(0)?)
+        } else {
+            SketchView::new(keyed::split(body).0).at(0)?;
+            None
+        };
+        let json = || match &timeline {
+            Some(view) => json_from_timeline(view),
+            None => {
+                let (regs, key_id) = keyed::split(body);
+                json_from_blob(regs, key_id)
+            }
+        };
+        // The base64-encoding is that of the blob, trailer included, the
+        // JSON-encoding lists registers only
+        let base64 = || base64::encode(compress::compress(&buf).unwrap_or_else(|| buf.to_vec()));
+        let text = match args.get(1).map(|v| RawValue::new(*v)).transpose()? {
+            None => base64(),
+            Some(RawValue::Text(f)) if f.eq_ignore_ascii_case("base64") => base64(),
+            Some(RawValue::Text(f)) if f.eq_ignore_ascii_case("json") => json().to_string(),
+            Some(other) => return Err(HMHError::UnknownTextFormat(other).at(1)),
+        };
+        set_text_result(ctx, &text);
+        Ok(())
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_from_text(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let buf = stored_bytes(args[0], timeline::MAX_SIZE).at(0)?;
+        check_format(integrity::verify(None, &buf).at(0)?).at(0)?;
+        set_blob_slice_result(
+            ctx,
+            &compress::compress(&buf).unwrap_or_else(|| buf.to_vec()),
+        );
+        Ok(())
+    });
+}
+
+/// A sketch as a base64-encoded JSON-string, encoded as HYPERMINHASH_SERIALIZE() does
+fn encoded_sketch(sketch: &SparseSketch) -> serde_json::Value {
+    base64::encode(encode_sketch(sketch)).into()
+}
+
+/// Finalize HYPERMINHASH_COLUMNS_SERIALIZE() into the base64-encoded sketch per column
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_columns_serialize_final(ctx: *mut sqlite3_context) {
+    HMHError::set_ctx(ctx, || {
+        let columns = take_columns(ctx);
+        let encoded = columns
+            .sketches
+            .iter()
+            .map(encoded_sketch)
+            .collect::<Vec<_>>();
+        set_text_result(ctx, &columns_object(encoded));
+        Ok(())
+    })
+}
+
+/// Finalize HYPERMINHASH_GROUPED_SERIALIZE() into the base64-encoded sketch per key
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_grouped_serialize_final(ctx: *mut sqlite3_context) {
+    HMHError::set_ctx(ctx, || {
+        let groups = take_groups(ctx);
+        let encoded = groups
+            .into_sketches()
+            .map(|(key, sketch)| (key, encoded_sketch(&sketch)))
+            .collect::<Vec<_>>();
+        set_text_result(ctx, &json_object(encoded));
+        Ok(())
+    })
+}
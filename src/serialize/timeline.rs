@@ -0,0 +1,543 @@
+//! Timelines: one sketch per fixed-width time-bucket
+//!
+//! A serialized timeline is
+//!
+//! * the magic `HMHT`
+//! * the format-version as a little-endian `u32`
+//! * the bucket-width as a little-endian `i64`
+//! * the key-tag of the key rows were hashed with, see `keyed`, or as many
+//!   zero-bytes if they were not keyed
+//! * for each bucket, ordered by time, the bucket's start as a little-endian `i64`
+//!   and the size of it's registers as a little-endian `u32`, followed by the
+//!   registers: all of them, as in a serialized sketch, or only the non-empty ones
+//!   as little-endian `u16`-pairs of index and value, ordered by index, if that
+//!   is smaller
+//!
+//! optionally followed by a checksum or signature over all of the above, as for
+//! sketches, see `integrity`.
+//!
+//! Most buckets only see a small share of all rows, so most are stored sparse. A
+//! `TimelineView` reads a serialized timeline in place and only decodes the
+//! buckets asked for. A `Timeline` keeps all buckets as `SparseSketch`es, so it's
+//! memory is charged to the connection's memory budget, and has at most
+//! `MAX_BUCKETS` of them.
+use std::{collections::BTreeMap, mem, os::raw, sync::Arc};
+
+use super::super::alloc::Budget;
+use super::super::bindings::*;
+use super::super::settings::{self, HashKey};
+use super::super::sparse::{SparseSketch, REGISTERS};
+use super::super::{add_row, arguments, ArgumentError, HMHError, RawValue};
+use super::keyed::{self, KeyId};
+use super::{compress, integrity};
+use super::{set_blob_slice_result, stored_bytes, SKETCH_SIZE};
+
+pub(super) const MAGIC: &[u8; 4] = b"HMHT";
+pub(super) const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 16 + keyed::TAG_SIZE;
+/// A bucket's start and the size of it's registers
+const BUCKET_HEADER_SIZE: usize = mem::size_of::<i64>() + mem::size_of::<u32>();
+/// A non-empty register of a sparse bucket, it's index and value
+const ENTRY_SIZE: usize = 2 * mem::size_of::<u16>();
+/// The number of buckets a timeline may have, each taking up to a sketch's worth
+/// of memory
+pub(super) const MAX_BUCKETS: usize = 4096;
+/// The size of the largest serialized timeline, with it's trailer
+pub(super) const MAX_SIZE: usize =
+    HEADER_SIZE + MAX_BUCKETS * (BUCKET_HEADER_SIZE + SKETCH_SIZE) + integrity::MAX_TRAILER;
+
+fn corrupt<'a>(msg: &str) -> HMHError<'a> {
+    HMHError::Corrupt(msg.to_owned())
+}
+
+fn bucket_start(width: i64, ts: i64) -> i64 {
+    ts.saturating_sub(ts.rem_euclid(width))
+}
+
+/// A serialized timeline without it's trailer, read in place
+pub(super) struct TimelineView<'b> {
+    pub width: i64,
+    pub key_id: KeyId,
+    header: &'b [u8],
+    buckets: Vec<Bucket<'b>>,
+}
+
+impl<'b> TimelineView<'b> {
+    /// Check the header and the layout of all buckets, but not their registers,
+    /// see `Bucket::check()`
+    pub(super) fn new<'a>(buf: &'b [u8]) -> Result<Self, HMHError<'a>> {
+        if !buf.starts_with(MAGIC) || buf.len() < HEADER_SIZE {
+            return Err(corrupt("not a serialized timeline"));
+        }
+        if buf[4..8] != VERSION.to_le_bytes() {
+            return Err(corrupt("unsupported timeline-version"));
+        }
+        let width = read_i64(&buf[8..16]);
+        if width <= 0 {
+            return Err(HMHError::Timeline("bucket-width must be positive"));
+        }
+        let key_id = match &buf[16..HEADER_SIZE] {
+            tag if tag.iter().all(|b| *b == 0) => None,
+            tag => Some(keyed::parse_tag(tag).ok_or_else(|| corrupt("malformed key-tag"))?),
+        };
+        let (header, mut rest) = buf.split_at(HEADER_SIZE);
+        let mut buckets: Vec<Bucket> = Vec::new();
+        while !rest.is_empty() {
+            if buckets.len() == MAX_BUCKETS {
+                return Err(HMHError::Timeline("too many buckets"));
+            }
+            if rest.len() < BUCKET_HEADER_SIZE {
+                return Err(corrupt("truncated bucket"));
+            }
+            let start = read_i64(&rest[..8]);
+            let size = u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]) as usize;
+            if size != SKETCH_SIZE && (!size.is_multiple_of(ENTRY_SIZE) || size > SKETCH_SIZE) {
+                return Err(corrupt("malformed bucket"));
+            }
+            if rest.len() - BUCKET_HEADER_SIZE < size {
+                return Err(corrupt("truncated bucket"));
+            }
+            let ordered = !matches!(buckets.last(), Some(last) if last.start >= start);
+            if start != bucket_start(width, start) || !ordered {
+                return Err(corrupt("misaligned or unordered bucket"));
+            }
+            let (raw, remaining) = rest.split_at(BUCKET_HEADER_SIZE + size);
+            buckets.push(Bucket { start, raw });
+            rest = remaining;
+        }
+        Ok(Self {
+            width,
+            key_id,
+            header,
+            buckets,
+        })
+    }
+
+    /// As `new()`, checking the registers of all buckets as well
+    pub(super) fn checked<'a>(buf: &'b [u8]) -> Result<Self, HMHError<'a>> {
+        let view = Self::new(buf)?;
+        view.buckets().try_for_each(Bucket::check)?;
+        Ok(view)
+    }
+
+    pub(super) fn buckets(&self) -> impl DoubleEndedIterator<Item = Bucket<'b>> + '_ {
+        self.buckets.iter().copied()
+    }
+
+    /// The buckets overlapping the (inclusive) time-range
+    fn range(&self, from: i64, to: i64) -> impl Iterator<Item = Bucket<'b>> + '_ {
+        let first = bucket_start(self.width, from);
+        self.buckets()
+            .filter(move |b| from <= to && b.start >= first && b.start <= to)
+    }
+}
+
+/// A bucket of a serialized timeline
+#[derive(Clone, Copy)]
+pub(super) struct Bucket<'b> {
+    pub start: i64,
+    /// The whole serialized bucket, it's start and size included
+    raw: &'b [u8],
+}
+
+impl<'b> Bucket<'b> {
+    fn stored(self) -> &'b [u8] {
+        &self.raw[BUCKET_HEADER_SIZE..]
+    }
+
+    fn is_dense(self) -> bool {
+        self.stored().len() == SKETCH_SIZE
+    }
+
+    /// Check that the non-empty registers of a sparse bucket are ordered by index,
+    /// without repetition, and not empty after all
+    pub(super) fn check<'a>(self) -> Result<(), HMHError<'a>> {
+        if self.is_dense() {
+            return Ok(());
+        }
+        let mut next = 0;
+        for entry in self.stored().chunks_exact(ENTRY_SIZE) {
+            let idx = usize::from(u16::from_le_bytes([entry[0], entry[1]]));
+            if idx < next || idx >= REGISTERS || entry[2..] == [0, 0] {
+                return Err(corrupt("malformed sparse bucket"));
+            }
+            next = idx + 1;
+        }
+        Ok(())
+    }
+
+    /// All registers, in order, of a bucket that passed `check()`
+    pub(super) fn registers(self) -> BucketRegisters<'b> {
+        BucketRegisters {
+            dense: self.is_dense(),
+            stored: self.stored(),
+            idx: 0,
+        }
+    }
+}
+
+/// The registers of a bucket, see `Bucket::registers()`
+#[derive(Clone)]
+pub(super) struct BucketRegisters<'b> {
+    dense: bool,
+    /// All registers if dense, the entries not reached yet if sparse
+    stored: &'b [u8],
+    idx: usize,
+}
+
+impl<'b> Iterator for BucketRegisters<'b> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        if self.idx == REGISTERS {
+            return None;
+        }
+        let idx = self.idx;
+        self.idx += 1;
+        if self.dense {
+            return Some(u16::from_le_bytes([
+                self.stored[idx * 2],
+                self.stored[idx * 2 + 1],
+            ]));
+        }
+        match self.stored {
+            [lo, hi, r0, r1, rest @ ..] if usize::from(u16::from_le_bytes([*lo, *hi])) == idx => {
+                self.stored = rest;
+                Some(u16::from_le_bytes([*r0, *r1]))
+            }
+            _ => Some(0),
+        }
+    }
+}
+
+/// The header of a serialized timeline
+pub(super) fn encode_header(width: i64, key_id: KeyId) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_SIZE);
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&width.to_le_bytes());
+    buf.extend_from_slice(&keyed::tag(key_id));
+    buf.resize(HEADER_SIZE, 0);
+    buf
+}
+
+/// Append a bucket to a serialized timeline, sparse if that is smaller
+pub(super) fn encode_bucket<I: Iterator<Item = u16>>(
+    buf: &mut Vec<u8>,
+    start: i64,
+    registers: impl Fn() -> I,
+) {
+    let used = registers().filter(|reg| *reg != 0).count();
+    let size = (used * ENTRY_SIZE).min(SKETCH_SIZE);
+    buf.extend_from_slice(&start.to_le_bytes());
+    buf.extend_from_slice(&(size as u32).to_le_bytes());
+    if size == SKETCH_SIZE {
+        registers().for_each(|reg| buf.extend_from_slice(&reg.to_le_bytes()));
+    } else {
+        for (idx, reg) in registers().enumerate().filter(|(_, reg)| *reg != 0) {
+            buf.extend_from_slice(&(idx as u16).to_le_bytes());
+            buf.extend_from_slice(&reg.to_le_bytes());
+        }
+    }
+}
+
+pub(super) struct Timeline {
+    pub width: i64,
+    /// The key rows are hashed with; only known for timelines built from rows
+    key: Option<HashKey>,
+    pub key_id: KeyId,
+    pub buckets: BTreeMap<i64, SparseSketch>,
+    budget: Option<Arc<Budget>>,
+}
+
+impl Timeline {
+    fn new<'a>(
+        width: i64,
+        key_id: KeyId,
+        budget: Option<Arc<Budget>>,
+    ) -> Result<Self, HMHError<'a>> {
+        if width <= 0 {
+            return Err(HMHError::Timeline("bucket-width must be positive"));
+        }
+        Ok(Self {
+            width,
+            key: None,
+            key_id,
+            buckets: BTreeMap::new(),
+            budget,
+        })
+    }
+
+    /// The bucket `ts` falls into, added if there is none yet
+    fn bucket<'a>(&mut self, ts: i64) -> Result<&mut SparseSketch, HMHError<'a>> {
+        let start = bucket_start(self.width, ts);
+        if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(&start) {
+            return Err(HMHError::Timeline(
+                "too many buckets, use a wider bucket-width",
+            ));
+        }
+        let (budget, key) = (&self.budget, self.key);
+        Ok(self
+            .buckets
+            .entry(start)
+            .or_insert_with(|| SparseSketch::new(budget.clone(), key)))
+    }
+
+    /// Load all buckets of a serialized timeline, charging them to the given budget
+    fn load<'a>(view: &TimelineView, budget: Option<Arc<Budget>>) -> Result<Self, HMHError<'a>> {
+        let mut timeline = Self::new(view.width, view.key_id, budget)?;
+        for bucket in view.buckets() {
+            bucket.check()?;
+            timeline.bucket(bucket.start)?.union(bucket.registers())?;
+        }
+        Ok(timeline)
+    }
+
+    /// The serialized timeline, compressed if that pays off
+    fn save(&self) -> Vec<u8> {
+        let mut buf = encode_header(self.width, self.key_id);
+        for (start, sketch) in &self.buckets {
+            encode_bucket(&mut buf, *start, || sketch.registers());
+        }
+        compress::compress(&buf).unwrap_or(buf)
+    }
+
+    /// Merge the matching buckets of both timelines
+    fn union<'a>(&mut self, other: &Self) -> Result<(), HMHError<'a>> {
+        if self.width != other.width {
+            return Err(HMHError::Timeline("bucket-widths differ"));
+        }
+        keyed::common([self.key_id, other.key_id])?;
+        for (start, sketch) in &other.buckets {
+            self.bucket(*start)?.union(sketch.registers())?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the serialized timeline, without it's trailer, may be of this size
+pub(super) fn is_size(buf: &[u8], len: usize) -> bool {
+    TimelineView::new(&buf[..len]).is_ok()
+}
+
+fn read_i64(buf: &[u8]) -> i64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(buf);
+    i64::from_le_bytes(b)
+}
+
+/// A point in time; `REAL`s are truncated to whole units
+unsafe fn timestamp<'a>(value: *mut sqlite3_value) -> Result<Option<i64>, HMHError<'a>> {
+    match RawValue::new(value)? {
+        RawValue::Null => Ok(None),
+        RawValue::Int(i) => Ok(Some(i)),
+        RawValue::Float(f) => Ok(Some(f64::from_bits(f).floor() as i64)),
+        other => Err(HMHError::UnexpectedType(
+            "an INTEGER or REAL timestamp",
+            other,
+        )),
+    }
+}
+
+unsafe fn timeline_from_value<'a>(
+    ctx: *mut sqlite3_context,
+    value: *mut sqlite3_value,
+) -> Result<Timeline, HMHError<'a>> {
+    with_timeline_view(value, |view| {
+        Timeline::load(&view, Some(Budget::of_connection(ctx)))
+    })
+}
+
+/// Read a serialized timeline in place, from a `BLOB` or any of it's
+/// text-encodings
+unsafe fn with_timeline_view<'a, T>(
+    value: *mut sqlite3_value,
+    f: impl FnOnce(TimelineView) -> Result<T, HMHError<'a>>,
+) -> Result<T, HMHError<'a>> {
+    let buf = stored_bytes(value, MAX_SIZE)?;
+    f(TimelineView::new(integrity::verify(None, &buf)?)?)
+}
+
+unsafe fn timeline_to_result<'a>(
+    timeline: &Timeline,
+    ctx: *mut sqlite3_context,
+) -> Result<(), HMHError<'a>> {
+    set_blob_slice_result(ctx, &timeline.save());
+    Ok(())
+}
+
+/// The step-function of HYPERMINHASH_TIMELINE(ts, bucket_width, values...)
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_timeline_step(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 2..=usize::MAX)?;
+        let ts = match timestamp(args[0]).at(0)? {
+            Some(ts) => ts,
+            None => return Ok(()), // Rows without a point in time are not counted
+        };
+        let width = match RawValue::new(args[1])? {
+            RawValue::Int(i) => i,
+            other => return Err(HMHError::UnexpectedType("an INTEGER bucket-width", other).at(1)),
+        };
+
+        let p = sqlite3_aggregate_context(ctx, mem::size_of::<*mut Timeline>() as raw::c_int)
+            as *mut *mut Timeline;
+        if p.is_null() {
+            sqlite3_result_error_nomem(ctx);
+            return Ok(());
+        }
+        if (*p).is_null() {
+            let key = settings::hash_key(ctx)?;
+            let mut timeline =
+                Timeline::new(width, key.map(|k| k.id), Some(Budget::of_connection(ctx)))?;
+            timeline.key = key;
+            *p = Box::into_raw(Box::new(timeline));
+        }
+        let timeline = &mut **p;
+        if timeline.width != width {
+            return Err(HMHError::Timeline("bucket-width changed between rows"));
+        }
+        add_row(timeline.bucket(ts)?, &args[2..])
+    })
+}
+
+/// Shared by HYPERMINHASH_TIMELINE() and HYPERMINHASH_TIMELINE_UNION(); NULL if
+/// there were no rows
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_timeline_final(ctx: *mut sqlite3_context) {
+    HMHError::set_ctx(ctx, || {
+        let p = sqlite3_aggregate_context(ctx, 0) as *mut *mut Timeline;
+        if p.is_null() || (*p).is_null() {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        timeline_to_result(&Box::from_raw(*p), ctx)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_timeline_union_step(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        let timeline = timeline_from_value(ctx, args[0]).at(0)?;
+
+        let p = sqlite3_aggregate_context(ctx, mem::size_of::<*mut Timeline>() as raw::c_int)
+            as *mut *mut Timeline;
+        if p.is_null() {
+            sqlite3_result_error_nomem(ctx);
+            return Ok(());
+        }
+        if (*p).is_null() {
+            *p = Box::into_raw(Box::new(timeline));
+            Ok(())
+        } else {
+            (**p).union(&timeline)
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_timeline_add(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let (first, rest) = arguments(values, num_values, 1..=usize::MAX)?
+            .split_first()
+            .unwrap();
+        let mut timeline = timeline_from_value(ctx, *first).at(0)?;
+        for (i, v) in rest.iter().enumerate() {
+            timeline.union(&timeline_from_value(ctx, *v).at(i + 1)?)?;
+        }
+        timeline_to_result(&timeline, ctx)
+    })
+}
+
+/// The approximate cardinality over all buckets overlapping [from, to]; NULL
+/// leaves the range open
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_range(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 3..=3)?;
+        let from = timestamp(args[1]).at(1)?.unwrap_or(i64::MIN);
+        let to = timestamp(args[2]).at(2)?.unwrap_or(i64::MAX);
+        let sketch = with_timeline_view(args[0], |view| {
+            let mut sketch = SparseSketch::new(Some(Budget::of_connection(ctx)), None);
+            for bucket in view.range(from, to) {
+                bucket.check()?;
+                sketch.union(bucket.registers())?;
+            }
+            Ok(sketch)
+        })
+        .at(0)?;
+        sqlite3_result_double(ctx, sketch.cardinality());
+        Ok(())
+    })
+}
+
+/// Drop all buckets which end at or before the given point in time
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_timeline_expire(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 2..=2)?;
+        let before = timestamp(args[1]).at(1)?.unwrap_or(i64::MIN);
+        let expired = with_timeline_view(args[0], |view| {
+            // The retained buckets are copied as they are stored
+            let mut buf = view.header.to_vec();
+            for bucket in view.buckets() {
+                if bucket.start.saturating_add(view.width) > before {
+                    bucket.check()?;
+                    buf.extend_from_slice(bucket.raw);
+                }
+            }
+            Ok(buf)
+        })
+        .at(0)?;
+        set_blob_slice_result(ctx, &compress::compress(&expired).unwrap_or(expired));
+        Ok(())
+    })
+}
+
+/// Merge buckets into wider ones; the new width must be a multiple of the old
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_timeline_compact(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 2..=2)?;
+        let timeline = timeline_from_value(ctx, args[0]).at(0)?;
+        let width = match RawValue::new(args[1])? {
+            RawValue::Int(i) => i,
+            other => return Err(HMHError::UnexpectedType("an INTEGER bucket-width", other).at(1)),
+        };
+        if width <= 0 || width % timeline.width != 0 {
+            return Err(HMHError::Timeline(
+                "new bucket-width must be a multiple of the old",
+            ));
+        }
+        let mut compacted = Timeline::new(width, timeline.key_id, timeline.budget.clone())?;
+        for (start, sketch) in &timeline.buckets {
+            compacted.bucket(*start)?.union(sketch.registers())?;
+        }
+        timeline_to_result(&compacted, ctx)
+    })
+}
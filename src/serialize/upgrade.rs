@@ -0,0 +1,210 @@
+//! Converting stored sketches to the current encoding
+//!
+//! Sketches stored before compression was turned on are bare registers, which all
+//! functions keep accepting. Upgrading re-encodes them as results are encoded now.
+//! A sketch keeps it's key-tag and trailer, if any; a checksum has to match, a
+//! signature is kept unchecked, as the registers it covers don't change.
+//!
+//! Sketches are only signed on request, by passing a true `sign`-argument, never
+//! because an HMAC-key happens to be set: signing whatever is stored would vouch
+//! for sketches nobody checked, including those whose signature was stripped.
+use std::os::raw;
+use std::sync::Arc;
+
+use super::super::bindings::*;
+use super::super::profile::table_arg;
+use super::super::query::{execute, quote_ident, Statement};
+use super::super::{arguments, set_text_result, ArgumentError, HMHError, RawValue};
+use super::integrity::{hmac_key, sign};
+use super::view::SketchView;
+use super::{checked_registers, compress, set_blob_slice_result, sketch_bytes};
+
+/// Rows read and rewritten at a time, unless given
+const DEFAULT_BATCH: i64 = 1000;
+
+/// The sketch in the current encoding, given the serialized sketch in any of it's
+/// current or former encodings, inflated; signed with the key, if given
+fn upgrade<'a>(buf: &[u8], key: Option<&[u8]>) -> Result<Vec<u8>, HMHError<'a>> {
+    SketchView::new(checked_registers(buf)?.0)?;
+    let signed;
+    let buf = match key {
+        Some(key) => {
+            signed = sign(key, buf)?;
+            &signed[..]
+        }
+        None => buf,
+    };
+    Ok(compress::compress(buf).unwrap_or_else(|| buf.to_vec()))
+}
+
+/// The connection's HMAC-key if the optional `sign`-argument is true, which it
+/// has to be an INTEGER for
+unsafe fn signing_key<'a>(
+    ctx: *mut sqlite3_context,
+    arg: Option<*mut sqlite3_value>,
+) -> Result<Option<Arc<[u8]>>, HMHError<'a>> {
+    match arg.map(|v| RawValue::new(v)).transpose()? {
+        None | Some(RawValue::Int(0)) => Ok(None),
+        Some(RawValue::Int(_)) => hmac_key(ctx).map(Some),
+        Some(other) => Err(HMHError::UnexpectedType("an INTEGER", other)),
+    }
+}
+
+/// HYPERMINHASH_UPGRADE(sketch[, sign])
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_upgrade(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=2)?;
+        let key = signing_key(ctx, args.get(1).copied()).at(1)?;
+        if sqlite3_value_type(args[0]) as u32 == SQLITE_NULL {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        let buf = sketch_bytes(args[0]).at(0)?;
+        set_blob_slice_result(ctx, &upgrade(&buf, key.as_deref()).at(0)?);
+        Ok(())
+    })
+}
+
+#[derive(Default)]
+struct Report {
+    /// Non-NULL values seen
+    rows: i64,
+    /// Values rewritten
+    converted: i64,
+    /// Bytes no longer stored; negative if the column grew
+    bytes_saved: i64,
+}
+
+/// Re-encode all values of the column, reading `batch` rows at a time
+unsafe fn upgrade_rows<'a>(
+    db: *mut sqlite3,
+    table: &str,
+    column: &str,
+    batch: i64,
+    key: Option<&[u8]>,
+) -> Result<Report, HMHError<'a>> {
+    let (table, column) = (quote_ident(table), quote_ident(column));
+    // The first batch starts at the smallest possible rowid, the others after the
+    // last row seen
+    let select = |op| {
+        Statement::prepare(
+            db,
+            &format!(
+                "SELECT rowid, {c} FROM {t} WHERE rowid {op} ?1 AND {c} IS NOT NULL ORDER BY rowid LIMIT ?2",
+                t = table,
+                c = column,
+                op = op
+            ),
+        )
+    };
+    let (mut first, mut next) = (select(">=")?, select(">")?);
+    let mut select = &mut first;
+    let mut update = Statement::prepare(
+        db,
+        &format!("UPDATE {} SET {} = ?1 WHERE rowid = ?2", table, column),
+    )?;
+    let mut report = Report::default();
+    let mut last = i64::MIN;
+    loop {
+        select.bind_int(1, last)?;
+        select.bind_int(2, batch)?;
+        let mut upgraded = Vec::new();
+        let mut rows = 0;
+        while select.step()? {
+            rows += 1;
+            last = match RawValue::new(select.value(0))? {
+                RawValue::Int(rowid) => rowid,
+                other => return Err(HMHError::UnexpectedType("a rowid", other)),
+            };
+            let value = select.value(1);
+            let in_row = |e| match e {
+                HMHError::NoMem | HMHError::Panic(_) => e,
+                e => HMHError::Row(last, Box::new(e)),
+            };
+            let (stored, is_blob) = match RawValue::new(value)? {
+                RawValue::Blob(b) => (b, true),
+                RawValue::Text(s) => (s.as_bytes(), false),
+                other => return Err(in_row(HMHError::ValueIsNotBlob(other))),
+            };
+            let encoded = sketch_bytes(value)
+                .and_then(|buf| upgrade(&buf, key))
+                .map_err(in_row)?;
+            if is_blob && stored == &encoded[..] {
+                continue;
+            }
+            report.bytes_saved += stored.len() as i64 - encoded.len() as i64;
+            upgraded.push((last, encoded));
+        }
+        // Not scanning the table while it is updated
+        select.reset();
+        report.rows += rows;
+        for (rowid, encoded) in &upgraded {
+            update.bind_blob(1, encoded)?;
+            update.bind_int(2, *rowid)?;
+            while update.step()? {}
+            update.reset();
+        }
+        report.converted += upgraded.len() as i64;
+        if rows < batch {
+            return Ok(report);
+        }
+        select = &mut next;
+    }
+}
+
+/// HYPERMINHASH_UPGRADE_COLUMN(table, column[, batch[, sign]])
+///
+/// Upgrades all sketches in the column within a single savepoint, so either all or
+/// none are converted. The batch-size only bounds the number of rows read and held
+/// at a time; the savepoint, and the statement calling us, hold their locks and
+/// journal until all rows are done. Reports the rows seen and converted and the
+/// bytes saved.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_upgrade_column(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 2..=4)?;
+        let table = table_arg(args[0]).at(0)?;
+        let column = match RawValue::new(args[1])? {
+            RawValue::Text(s) => s,
+            other => return Err(HMHError::UnexpectedType("a column-name", other).at(1)),
+        };
+        let batch = match args.get(2).map(|v| RawValue::new(*v)).transpose()? {
+            None => DEFAULT_BATCH,
+            Some(RawValue::Int(n)) if n > 0 => n,
+            Some(RawValue::Int(_)) => {
+                return Err(HMHError::InvalidArgument("batch-size must be positive").at(2))
+            }
+            Some(other) => return Err(HMHError::UnexpectedType("an INTEGER", other).at(2)),
+        };
+        let key = signing_key(ctx, args.get(3).copied()).at(3)?;
+
+        let db = sqlite3_context_db_handle(ctx);
+        execute(db, "SAVEPOINT hyperminhash_upgrade")?;
+        let report = match upgrade_rows(db, table, column, batch, key.as_deref()) {
+            Ok(report) => report,
+            Err(e) => {
+                // The error is what's worth reporting, not a failed rollback
+                let _ = execute(db, "ROLLBACK TO hyperminhash_upgrade");
+                let _ = execute(db, "RELEASE hyperminhash_upgrade");
+                return Err(e);
+            }
+        };
+        execute(db, "RELEASE hyperminhash_upgrade")?;
+        let report = serde_json::json!({
+            "rows": report.rows,
+            "converted": report.converted,
+            "bytes_saved": report.bytes_saved,
+        });
+        set_text_result(ctx, &report.to_string());
+        Ok(())
+    })
+}
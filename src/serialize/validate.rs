@@ -0,0 +1,128 @@
+//! Checks for sketches from untrusted sources
+//!
+//! `Sketch::load` accepts any blob of the right size, so a crafted sketch with
+//! saturated registers inflates every union it is merged into. Besides the ranges
+//! of the registers, we check that the sketch is plausible: for `n` elements spread
+//! over `m` registers, a register's leading-zero count reaches `k` with probability
+//! `1 - exp(-n / m * 2^(1 - k))`. Taking `n` to be the sketch's own estimate, no
+//! more registers may reach `k` than that allows. A forged sketch which passes has
+//! to look like an honest sketch of a set that large.
+use std::os::raw;
+
+use super::super::bindings::*;
+use super::super::sparse::Estimate;
+use super::super::{arguments, set_text_result, ArgumentError, HMHError};
+use super::timeline::TimelineView;
+use super::view::SketchView;
+use super::{integrity, keyed, stored_bytes, timeline, LZ_SHIFT, PRECISION, REGISTERS};
+
+/// The largest leading-zero count of a 64-bit hash, after taking the index
+const MAX_LZ: u16 = 64 - PRECISION as u16 + 1;
+
+/// Standard deviations a register-count may exceed it's expectation by
+const TOLERANCE: f64 = 6.0;
+
+/// Check a serialized sketch or timeline, without it's trailer; the first
+/// problem found, if any
+pub(super) fn validate(buf: &[u8]) -> Result<(), String> {
+    if buf.starts_with(timeline::MAGIC) {
+        let view = TimelineView::checked(buf).map_err(|e| e.to_string())?;
+        return view.buckets().enumerate().try_for_each(|(i, b)| {
+            validate_registers(b.registers()).map_err(|e| format!("bucket {}: {}", i, e))
+        });
+    }
+    let view = SketchView::new(keyed::split(buf).0).map_err(|e| e.to_string())?;
+    validate_registers(view.registers())
+}
+
+fn validate_registers(registers: impl Iterator<Item = u16>) -> Result<(), String> {
+    // Number of registers whose leading-zero count is exactly `k`
+    let mut histogram = [0usize; MAX_LZ as usize + 1];
+    let mut estimate = Estimate::default();
+    for (idx, reg) in registers.enumerate() {
+        let lz = reg >> LZ_SHIFT;
+        if lz > MAX_LZ {
+            return Err(format!(
+                "register {} has a leading-zero count of {}, at most {} is possible",
+                idx, lz, MAX_LZ
+            ));
+        }
+        if lz == 0 && reg != 0 {
+            return Err(format!("register {} is empty but has a minhash", idx));
+        }
+        histogram[lz as usize] += 1;
+        estimate.add(reg);
+    }
+
+    let per_register = estimate.cardinality() / REGISTERS as f64;
+    let mut at_least = REGISTERS - histogram[0];
+    for k in 1..=MAX_LZ {
+        let p = -(-per_register * 2f64.powi(1 - i32::from(k))).exp_m1();
+        let expected = REGISTERS as f64 * p;
+        let bound = expected + TOLERANCE * (expected * (1.0 - p)).sqrt() + TOLERANCE;
+        if at_least as f64 > bound {
+            return Err(format!(
+                "{} registers have a leading-zero count of {} or more, about {:.0} expected",
+                at_least, k, expected
+            ));
+        }
+        at_least -= histogram[k as usize];
+    }
+    Ok(())
+}
+
+/// NULL if the sketch or timeline passes it's integrity-check and validation, a
+/// description of the first problem found otherwise
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_validate(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        if sqlite3_value_type(args[0]) as u32 == SQLITE_NULL {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        match stored_bytes(args[0], timeline::MAX_SIZE).at(0) {
+            Ok(buf) => match integrity::verify(None, &buf)
+                .map_err(|e| e.to_string())
+                .and_then(validate)
+            {
+                Ok(()) => sqlite3_result_null(ctx),
+                Err(e) => set_text_result(ctx, &e),
+            },
+            // Undecodable text or compressed data is reported, not raised
+            Err(HMHError::Argument(_, e))
+                if matches!(*e, HMHError::InvalidText(_) | HMHError::Corrupt(_)) =>
+            {
+                set_text_result(ctx, &e.to_string())
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    })
+}
+
+/// HYPERMINHASH_STRICT(sketch), the sketch or timeline as given if it passes
+/// it's integrity-check and validation; fails with the first problem found otherwise
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_strict(
+    ctx: *mut sqlite3_context,
+    num_values: raw::c_int,
+    values: *mut *mut sqlite3_value,
+) {
+    HMHError::set_ctx(ctx, || {
+        let args = arguments(values, num_values, 1..=1)?;
+        if sqlite3_value_type(args[0]) as u32 == SQLITE_NULL {
+            sqlite3_result_null(ctx);
+            return Ok(());
+        }
+        let buf = stored_bytes(args[0], timeline::MAX_SIZE).at(0)?;
+        validate(integrity::verify(None, &buf).at(0)?)
+            .map_err(|e| HMHError::Suspicious(e).at(0))?;
+        sqlite3_result_value(ctx, args[0]);
+        Ok(())
+    })
+}
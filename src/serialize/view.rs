@@ -0,0 +1,95 @@
+//! Read-only views of serialized sketches
+//!
+//! Counting, merging and intersecting sketches only ever walk the registers in
+//! order, which we can do straight from the bytes sqlite hands us instead of
+//! loading a `Sketch` first. The estimators are those of `hyperminhash::Sketch`,
+//! which keeps it's registers to itself.
+use super::super::sparse::Estimate;
+use super::super::HMHError;
+use super::{check_size, registers, LZ_SHIFT, PRECISION};
+
+const C: f64 = 0.169_919_487_159_739_1;
+/// Number of bits holding the leading-zero count
+const Q: u32 = 16 - LZ_SHIFT;
+
+/// A serialized sketch, borrowed from sqlite
+#[derive(Clone, Copy)]
+pub(super) struct SketchView<'a>(&'a [u8]);
+
+impl<'a> SketchView<'a> {
+    pub(super) fn new<'e>(buf: &'a [u8]) -> Result<Self, HMHError<'e>> {
+        check_size(buf)?;
+        Ok(Self(buf))
+    }
+
+    pub(super) fn registers(self) -> impl Iterator<Item = u16> + 'a {
+        registers(self.0)
+    }
+
+    pub(super) fn cardinality(self) -> f64 {
+        let mut estimate = Estimate::default();
+        self.registers().for_each(|reg| estimate.add(reg));
+        estimate.cardinality()
+    }
+
+    /// The approximate number of elements in both sets, in a single pass
+    pub(super) fn intersection(self, other: SketchView) -> f64 {
+        let (mut n, mut m, mut union) = (
+            Estimate::default(),
+            Estimate::default(),
+            Estimate::default(),
+        );
+        let (mut cc, mut cn) = (0, 0);
+        for (r, rr) in self.registers().zip(other.registers()) {
+            n.add(r);
+            m.add(rr);
+            union.add(r.max(rr));
+            if r != 0 && r == rr {
+                cc += 1;
+            }
+            if r != 0 || rr != 0 {
+                cn += 1;
+            }
+        }
+        let similarity = if cc == 0 {
+            0.0
+        } else {
+            let ec = approximate_expected_collisions(n.cardinality(), m.cardinality());
+            if (cc as f64) < ec {
+                0.0
+            } else {
+                (cc as f64 - ec) / cn as f64
+            }
+        };
+        similarity * union.cardinality() + 0.5
+    }
+}
+
+fn approximate_expected_collisions(n: f64, m: f64) -> f64 {
+    let (n, m) = (n.max(m), n.min(m));
+    if n > 2f64.powf(2f64.powf(f64::from(Q)) + f64::from(LZ_SHIFT)) {
+        f64::INFINITY
+    } else if n > 2f64.powf(f64::from(PRECISION) + 5.0) {
+        let d = (4.0 * n / m) / ((1.0 + n) / m).powi(2);
+        C * 2f64.powf(f64::from(PRECISION) - f64::from(LZ_SHIFT)) * d + 0.5
+    } else {
+        expected_collisions(n, m) / f64::from(PRECISION)
+    }
+}
+
+fn expected_collisions(n: f64, m: f64) -> f64 {
+    let (tq, tr) = (1u32 << Q, 1u32 << LZ_SHIFT);
+    let mut x = 0.0;
+    for i in 1..tq {
+        for j in 1..tr {
+            let j = f64::from(j);
+            let den = 2f64.powf(f64::from(PRECISION) + f64::from(LZ_SHIFT) + f64::from(i));
+            let b1 = (f64::from(tr) + j) / den;
+            let b2 = (f64::from(tr) + j + 1.0) / den;
+            let prx = (1.0 - b2).powf(n) - (1.0 - b1).powf(n);
+            let pry = (1.0 - b2).powf(m) - (1.0 - b1).powf(m);
+            x += prx * pry;
+        }
+    }
+    (x * f64::from(PRECISION)) + 0.5
+}
@@ -0,0 +1,175 @@
+//! Settings of each connection
+//!
+//! Settings are changed through functions like HYPERMINHASH_MEMORY_LIMIT(). They
+//! are kept by the connection's state, which every function registered by shim.c
+//! holds on to as it's user-data, and forgotten as the connection closes.
+//!
+//! The results of the functions registered as deterministic never depend on the
+//! settings; the memory-limit only decides whether they fail. Keys are only used by
+//! the functions which take them by name, like HYPERMINHASH_KEYED_SERIALIZE() and
+//! HYPERMINHASH_SIGN().
+use std::{
+    collections::BTreeMap,
+    ffi,
+    os::raw,
+    sync::{Arc, Mutex, Weak},
+};
+
+use super::alloc::Budget;
+use super::bindings::*;
+use super::{catch_panic, HMHError};
+
+/// The seed rows are hashed with under a secret, and the id sketches hashed with it
+/// are tagged with; see `serialize::keyed`
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct HashKey {
+    pub seed: u64,
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub id: u64,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Settings {
+    /// The key HYPERMINHASH_SIGN() and HYPERMINHASH_VERIFY() use
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub hmac_key: Option<Arc<[u8]>>,
+    /// The secret the keyed functions hash rows with, instead of the default seed
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub hash_key: Option<HashKey>,
+}
+
+/// The state of a connection, shared by all functions registered with it
+#[derive(Default)]
+pub(crate) struct Connection {
+    settings: Mutex<Arc<Settings>>,
+    /// The memory all sketches of the connection may hold, and hold right now
+    pub budget: Arc<Budget>,
+}
+
+/// What each function gets as it's user-data: it's name, to name it in error
+/// messages, the connection it was registered with and whether it hashes rows with
+/// the connection's hash-key
+pub(crate) struct Function {
+    pub name: ffi::CString,
+    connection: Arc<Connection>,
+    keyed: bool,
+}
+
+/// The connections the extension has been loaded into, to find a connection's
+/// state if the extension is loaded again; see `hyperminhash_function()`
+static CONNECTIONS: Mutex<BTreeMap<usize, Weak<Connection>>> = Mutex::new(BTreeMap::new());
+
+/// The function `ctx` belongs to, as registered by `hyperminhash_function()`
+pub(crate) unsafe fn function<'f>(ctx: *mut sqlite3_context) -> &'f Function {
+    &*(sqlite3_user_data(ctx) as *const Function)
+}
+
+impl Connection {
+    /// The connection's settings as of now
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub(crate) fn settings(&self) -> Arc<Settings> {
+        Arc::clone(&self.settings.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// The state of the connection `ctx` belongs to
+pub(crate) unsafe fn connection<'c>(ctx: *mut sqlite3_context) -> &'c Connection {
+    &function(ctx).connection
+}
+
+/// The state of the connection `db`, for those without a function's context, such
+/// as table-valued functions; `None` if the extension was never loaded into it
+pub(crate) fn connection_of_db(db: *mut sqlite3) -> Option<Arc<Connection>> {
+    let connections = CONNECTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    connections.get(&(db as usize)).and_then(Weak::upgrade)
+}
+
+/// The settings of the connection `ctx` belongs to
+#[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+pub(crate) unsafe fn get(ctx: *mut sqlite3_context) -> Arc<Settings> {
+    connection(ctx).settings()
+}
+
+/// The hash-key the function hashes rows with: the connection's for the keyed
+/// functions, which fail if none is set, and `None` for all others
+pub(crate) unsafe fn hash_key<'a>(
+    ctx: *mut sqlite3_context,
+) -> Result<Option<HashKey>, HMHError<'a>> {
+    if !function(ctx).keyed {
+        return Ok(None);
+    }
+    #[cfg(not(feature = "serialize"))]
+    return Err(HMHError::FeatureMissing);
+    #[cfg(feature = "serialize")]
+    match get(ctx).hash_key {
+        Some(key) => Ok(Some(key)),
+        None => Err(HMHError::NoHashKey),
+    }
+}
+
+/// Change the connection's settings, returning what `f` returns. Statements
+/// holding on to the previous settings keep them.
+#[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+pub(crate) unsafe fn update<T>(ctx: *mut sqlite3_context, f: impl FnOnce(&mut Settings) -> T) -> T {
+    let mut settings = connection(ctx)
+        .settings
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    f(Arc::make_mut(&mut settings))
+}
+
+/// The user-data of the function named `name`, registered with `db` by shim.c.
+/// All functions of a connection share it's state, which is created as the
+/// extension is loaded for the first time and dropped along with the last
+/// function as the connection closes; loading the extension again keeps it.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_function(
+    db: *mut sqlite3,
+    name: *const raw::c_char,
+) -> *mut ffi::c_void {
+    new_function(db, name, false)
+}
+
+/// As `hyperminhash_function()`, for the functions hashing rows with the
+/// connection's hash-key
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_keyed_function(
+    db: *mut sqlite3,
+    name: *const raw::c_char,
+) -> *mut ffi::c_void {
+    new_function(db, name, true)
+}
+
+unsafe fn new_function(
+    db: *mut sqlite3,
+    name: *const raw::c_char,
+    keyed: bool,
+) -> *mut ffi::c_void {
+    let mut connections = CONNECTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    connections.retain(|_, connection| connection.strong_count() > 0);
+    let connection = match connections.get(&(db as usize)).and_then(Weak::upgrade) {
+        Some(connection) => connection,
+        None => {
+            let connection = Arc::new(Connection::default());
+            connections.insert(db as usize, Arc::downgrade(&connection));
+            connection
+        }
+    };
+    let function = Function {
+        name: ffi::CStr::from_ptr(name).to_owned(),
+        connection,
+        keyed,
+    };
+    Box::into_raw(Box::new(function)) as *mut ffi::c_void
+}
+
+/// Drop the user-data of a function, called by sqlite as the function is replaced
+/// or the connection closes
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_function_destroy(p: *mut ffi::c_void) {
+    // There is no one to report an error to
+    let _ = catch_panic(|| {
+        drop(Box::from_raw(p as *mut Function));
+        Ok(())
+    });
+}
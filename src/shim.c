@@ -11,6 +11,16 @@ SQLITE_EXTENSION_INIT1
 
 void hyperminhash_step(sqlite3_context*, int, sqlite3_value**);
 void hyperminhash_final(sqlite3_context*);
+void hyperminhash_columns_step(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_columns_final(sqlite3_context*);
+void hyperminhash_analyze(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_grouped_step(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_grouped_final(sqlite3_context*);
+void hyperminhash_json_step(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_memory_limit(sqlite3_context*, int, sqlite3_value**);
+void *hyperminhash_function(sqlite3*, const char*);
+void *hyperminhash_keyed_function(sqlite3*, const char*);
+void hyperminhash_function_destroy(void*);
 
 // The following have error-throwing impls if `serialize`-feature is inactive
 void hyperminhash_zero(sqlite3_context*, int, sqlite3_value**);
@@ -19,6 +29,62 @@ void hyperminhash_deserialize(sqlite3_context*, int, sqlite3_value**);
 void hyperminhash_add(sqlite3_context*, int, sqlite3_value**);
 void hyperminhash_union_step(sqlite3_context*, int, sqlite3_value**);
 void hyperminhash_intersection(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_info(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_to_text(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_from_text(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_insert(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_insert_inplace(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_timeline_step(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_timeline_final(sqlite3_context*);
+void hyperminhash_timeline_union_step(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_timeline_add(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_range(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_timeline_expire(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_timeline_compact(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_columns_serialize_final(sqlite3_context*);
+void hyperminhash_grouped_serialize_final(sqlite3_context*);
+void hyperminhash_from_json(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_validate(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_strict(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_checksum(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_sign(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_verify(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_hmac_key(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_hash_key(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_upgrade(sqlite3_context*, int, sqlite3_value**);
+void hyperminhash_upgrade_column(sqlite3_context*, int, sqlite3_value**);
+
+// Methods shared by all table-valued functions, see vtab.rs
+int hyperminhash_vtab_connect(sqlite3*, void*, int, const char *const*, sqlite3_vtab**, char**);
+int hyperminhash_vtab_best_index(sqlite3_vtab*, sqlite3_index_info*);
+int hyperminhash_vtab_disconnect(sqlite3_vtab*);
+int hyperminhash_vtab_open(sqlite3_vtab*, sqlite3_vtab_cursor**);
+int hyperminhash_vtab_close(sqlite3_vtab_cursor*);
+int hyperminhash_vtab_filter(sqlite3_vtab_cursor*, int, const char*, int, sqlite3_value**);
+int hyperminhash_vtab_next(sqlite3_vtab_cursor*);
+int hyperminhash_vtab_eof(sqlite3_vtab_cursor*);
+int hyperminhash_vtab_column(sqlite3_vtab_cursor*, sqlite3_context*, int);
+int hyperminhash_vtab_rowid(sqlite3_vtab_cursor*, sqlite3_int64*);
+
+// The table-definitions, passed as client data to the module
+const void *hyperminhash_registers_vtab(void);
+const void *hyperminhash_profile_vtab(void);
+const void *hyperminhash_dependencies_vtab(void);
+
+static sqlite3_module hyperminhash_vtab_module = {
+    .iVersion = 0,
+    .xCreate = NULL, // eponymous-only
+    .xConnect = hyperminhash_vtab_connect,
+    .xBestIndex = hyperminhash_vtab_best_index,
+    .xDisconnect = hyperminhash_vtab_disconnect,
+    .xOpen = hyperminhash_vtab_open,
+    .xClose = hyperminhash_vtab_close,
+    .xFilter = hyperminhash_vtab_filter,
+    .xNext = hyperminhash_vtab_next,
+    .xEof = hyperminhash_vtab_eof,
+    .xColumn = hyperminhash_vtab_column,
+    .xRowid = hyperminhash_vtab_rowid,
+};
 
 int init_shim(
   sqlite3 *db,
@@ -33,16 +99,95 @@ int init_shim(
       return SQLITE_ERROR;
   }
 
+  // Functions get their own name and the connection's state as pApp, see
+  // settings.rs. The state is dropped along with the last function as the
+  // connection closes, and kept if the extension is loaded again.
+  //
+  // Functions whose results depend on the connection's keys are not deterministic,
+  // and neither are those changing the connection or the database.
   rc = sqlite3_create_function_v2(
           db, // db
           "hyperminhash", // zFunctionName
           -1, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash"), // pApp
           NULL, // xFunc
           hyperminhash_step, // xStep
           hyperminhash_final, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_columns", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_columns"), // pApp
+          NULL, // xFunc
+          hyperminhash_columns_step, // xStep
+          hyperminhash_columns_final, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_grouped", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_grouped"), // pApp
+          NULL, // xFunc
+          hyperminhash_grouped_step, // xStep
+          hyperminhash_grouped_final, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  // The optional second argument is the JSON-path
+  for (int n_arg = 1; n_arg <= 2; n_arg++) {
+      rc = sqlite3_create_function_v2(
+              db, // db
+              "hyperminhash_json", // zFunctionName
+              n_arg, // nArg
+              SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+              hyperminhash_function(db, "hyperminhash_json"), // pApp
+              NULL, // xFunc
+              hyperminhash_json_step, // xStep
+              hyperminhash_final, // xFinal
+              hyperminhash_function_destroy // xDestroy
+              );
+      if (rc != SQLITE_OK)
+          return rc;
+  }
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_memory_limit", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it changes the connection
+          hyperminhash_function(db, "hyperminhash_memory_limit"), // pApp
+          hyperminhash_memory_limit, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_analyze", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it writes to the database
+          hyperminhash_function(db, "hyperminhash_analyze"), // pApp
+          hyperminhash_analyze, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
           );
   if (rc != SQLITE_OK)
       return rc;
@@ -52,11 +197,11 @@ int init_shim(
           "hyperminhash_zero", // zFunctionName
           0, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash_zero"), // pApp
           hyperminhash_zero, // xFunc
           NULL, // xStep
           NULL, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
           );
   if (rc != SQLITE_OK)
       return rc;
@@ -66,11 +211,11 @@ int init_shim(
           "hyperminhash_add", // zFunctionName
           -1, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash_add"), // pApp
           hyperminhash_add, // xFunc
           NULL, // xStep
           NULL, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
           );
   if (rc != SQLITE_OK)
       return rc;
@@ -80,11 +225,11 @@ int init_shim(
           "hyperminhash_serialize", // zFunctionName
           -1, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash_serialize"), // pApp
           NULL, // xFunc
           hyperminhash_step, // xStep
           hyperminhash_serialize_final, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
           );
   if (rc != SQLITE_OK)
       return rc;
@@ -94,11 +239,11 @@ int init_shim(
           "hyperminhash_deserialize", // zFunctionName
           1, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash_deserialize"), // pApp
           hyperminhash_deserialize, // xFunc
           NULL, // xStep
           NULL, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
           );
   if (rc != SQLITE_OK)
       return rc;
@@ -108,24 +253,427 @@ int init_shim(
           "hyperminhash_union", // zFunctionName
           1, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash_union"), // pApp
           NULL, // xFunc
           hyperminhash_union_step, // xStep
           hyperminhash_serialize_final, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
           );
   if (rc != SQLITE_OK)
       return rc;
 
-  return sqlite3_create_function_v2(
+  rc = sqlite3_create_function_v2(
           db, // db
           "hyperminhash_intersection", // zFunctionName
           2, // nArg
           SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
-          NULL, // pApp
+          hyperminhash_function(db, "hyperminhash_intersection"), // pApp
           hyperminhash_intersection, // xFunc
           NULL, // xStep
           NULL, // xFinal
-          NULL // xDestroy
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_info", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_info"), // pApp
+          hyperminhash_info, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_validate", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_validate"), // pApp
+          hyperminhash_validate, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_strict", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_strict"), // pApp
+          hyperminhash_strict, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_checksum", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_checksum"), // pApp
+          hyperminhash_checksum, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_hmac_key", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it changes the connection
+          hyperminhash_function(db, "hyperminhash_hmac_key"), // pApp
+          hyperminhash_hmac_key, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_hash_key", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it changes the connection
+          hyperminhash_function(db, "hyperminhash_hash_key"), // pApp
+          hyperminhash_hash_key, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_sign", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it uses the HMAC-key
+          hyperminhash_function(db, "hyperminhash_sign"), // pApp
+          hyperminhash_sign, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_verify", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it uses the HMAC-key
+          hyperminhash_function(db, "hyperminhash_verify"), // pApp
+          hyperminhash_verify, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_upgrade", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_upgrade"), // pApp
+          hyperminhash_upgrade, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_upgrade", // zFunctionName
+          2, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it may use the HMAC-key
+          hyperminhash_function(db, "hyperminhash_upgrade"), // pApp
+          hyperminhash_upgrade, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_upgrade_column", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it writes to the database
+          hyperminhash_function(db, "hyperminhash_upgrade_column"), // pApp
+          hyperminhash_upgrade_column, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  // The optional second argument selects the text-format
+  for (int n_arg = 1; n_arg <= 2; n_arg++) {
+      rc = sqlite3_create_function_v2(
+              db, // db
+              "hyperminhash_to_text", // zFunctionName
+              n_arg, // nArg
+              SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+              hyperminhash_function(db, "hyperminhash_to_text"), // pApp
+              hyperminhash_to_text, // xFunc
+              NULL, // xStep
+              NULL, // xFinal
+              hyperminhash_function_destroy // xDestroy
+              );
+      if (rc != SQLITE_OK)
+          return rc;
+  }
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_from_text", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_from_text"), // pApp
+          hyperminhash_from_text, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_insert", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_insert"), // pApp
+          hyperminhash_insert, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_insert_inplace", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8, // eTextRep, not deterministic as it writes to the database
+          hyperminhash_function(db, "hyperminhash_insert_inplace"), // pApp
+          hyperminhash_insert_inplace, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_timeline", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_timeline"), // pApp
+          NULL, // xFunc
+          hyperminhash_timeline_step, // xStep
+          hyperminhash_timeline_final, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_timeline_union", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_timeline_union"), // pApp
+          NULL, // xFunc
+          hyperminhash_timeline_union_step, // xStep
+          hyperminhash_timeline_final, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_timeline_add", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_timeline_add"), // pApp
+          hyperminhash_timeline_add, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_range", // zFunctionName
+          3, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_range"), // pApp
+          hyperminhash_range, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_timeline_expire", // zFunctionName
+          2, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_timeline_expire"), // pApp
+          hyperminhash_timeline_expire, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_timeline_compact", // zFunctionName
+          2, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_timeline_compact"), // pApp
+          hyperminhash_timeline_compact, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_columns_serialize", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_columns_serialize"), // pApp
+          NULL, // xFunc
+          hyperminhash_columns_step, // xStep
+          hyperminhash_columns_serialize_final, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_grouped_serialize", // zFunctionName
+          -1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_grouped_serialize"), // pApp
+          NULL, // xFunc
+          hyperminhash_grouped_step, // xStep
+          hyperminhash_grouped_serialize_final, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_function_v2(
+          db, // db
+          "hyperminhash_from_json", // zFunctionName
+          1, // nArg
+          SQLITE_UTF8 | WEAK_DETERMINISTIC, // eTextRep
+          hyperminhash_function(db, "hyperminhash_from_json"), // pApp
+          hyperminhash_from_json, // xFunc
+          NULL, // xStep
+          NULL, // xFinal
+          hyperminhash_function_destroy // xDestroy
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  // The keyed variants hash rows with the connection's hash-key, see keyed.rs;
+  // not deterministic as the key may change between statements
+  static const struct {
+      const char *name;
+      int n_arg;
+      void (*xFunc)(sqlite3_context*, int, sqlite3_value**);
+      void (*xStep)(sqlite3_context*, int, sqlite3_value**);
+      void (*xFinal)(sqlite3_context*);
+  } keyed[] = {
+      {"hyperminhash_keyed_zero", 0, hyperminhash_zero, NULL, NULL},
+      {"hyperminhash_keyed_serialize", -1, NULL, hyperminhash_step, hyperminhash_serialize_final},
+      {"hyperminhash_keyed_insert", -1, hyperminhash_insert, NULL, NULL},
+      {"hyperminhash_keyed_from_json", 1, hyperminhash_from_json, NULL, NULL},
+      {"hyperminhash_keyed_timeline", -1, NULL, hyperminhash_timeline_step, hyperminhash_timeline_final},
+      {"hyperminhash_keyed_columns_serialize", -1, NULL, hyperminhash_columns_step, hyperminhash_columns_serialize_final},
+      {"hyperminhash_keyed_grouped_serialize", -1, NULL, hyperminhash_grouped_step, hyperminhash_grouped_serialize_final},
+  };
+  for (size_t i = 0; i < sizeof(keyed) / sizeof(keyed[0]); i++) {
+      rc = sqlite3_create_function_v2(
+              db, // db
+              keyed[i].name, // zFunctionName
+              keyed[i].n_arg, // nArg
+              SQLITE_UTF8, // eTextRep
+              hyperminhash_keyed_function(db, keyed[i].name), // pApp
+              keyed[i].xFunc, // xFunc
+              keyed[i].xStep, // xStep
+              keyed[i].xFinal, // xFinal
+              hyperminhash_function_destroy // xDestroy
+              );
+      if (rc != SQLITE_OK)
+          return rc;
+  }
+
+  // Eponymous virtual tables require sqlite 3.9.0
+  if (sqlite3_libversion_number() < 3009000)
+      return SQLITE_OK;
+
+  rc = sqlite3_create_module(
+          db, // db
+          "hyperminhash_registers", // zName
+          &hyperminhash_vtab_module, // p
+          (void*)hyperminhash_registers_vtab() // pClientData
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  rc = sqlite3_create_module(
+          db, // db
+          "hyperminhash_profile", // zName
+          &hyperminhash_vtab_module, // p
+          (void*)hyperminhash_profile_vtab() // pClientData
+          );
+  if (rc != SQLITE_OK)
+      return rc;
+
+  return sqlite3_create_module(
+          db, // db
+          "hyperminhash_dependencies", // zName
+          &hyperminhash_vtab_module, // p
+          (void*)hyperminhash_dependencies_vtab() // pClientData
           );
 }
@@ -0,0 +1,199 @@
+//! Sketches which start out small
+//!
+//! A `Sketch` takes 32 KiB from it's first row on, which adds up quickly if a
+//! GROUP BY has many small groups. A `SparseSketch` only keeps it's non-empty
+//! registers until there are so many of them that the dense layout is cheaper.
+//! Rows are hashed and estimated exactly as `hyperminhash::Sketch` does, which
+//! keeps it's registers to itself.
+use std::{hash::Hash, sync::Arc};
+
+use super::alloc::{Budget, Buf};
+use super::settings::HashKey;
+use super::HMHError;
+
+pub(crate) const PRECISION: u32 = 14;
+/// Number of registers in a `Sketch`
+pub(crate) const REGISTERS: usize = 1 << PRECISION;
+/// The upper six bits of a register hold the leading-zero count
+pub(crate) const LZ_SHIFT: u32 = 10;
+/// Beyond this many non-empty registers, a sparse sketch takes half the memory of
+/// a dense one and becomes dense; it's memory is allocated by sqlite, see `alloc`
+const SPARSE_MAX: usize = REGISTERS / 4;
+
+const ALPHA: f64 = 0.7213 / (1.0 + 1.079 / REGISTERS as f64);
+
+/// Something rows can be counted in
+pub(crate) trait Counter {
+    fn add<'a>(&mut self, v: impl Hash) -> Result<(), HMHError<'a>>;
+}
+
+/// The register-index and -value a row's hash maps to
+pub(crate) fn register_update(hash: u128) -> (usize, u16) {
+    let x = hash as u64;
+    let y = (hash >> 64) as u64;
+    let idx = x >> (64 - PRECISION);
+    let lz = ((x << PRECISION) ^ (u64::MAX >> (64 - PRECISION))).leading_zeros() as u16 + 1;
+    let sig = (y & ((1 << LZ_SHIFT) - 1)) as u16;
+    (idx as usize, (lz << LZ_SHIFT) | sig)
+}
+
+/// The hash `Sketch::add_with_seed` computes for a value; seed 0 is that of `Sketch::add`
+pub(crate) fn hash(v: impl Hash, seed: u64) -> u128 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::with_seed(seed);
+    v.hash(&mut hasher);
+    hasher.digest128()
+}
+
+enum Registers {
+    /// The non-empty registers as (index, value), ordered by index
+    Sparse(Buf<(u16, u16)>),
+    Dense(Buf<u16>),
+}
+
+pub(crate) struct SparseSketch {
+    registers: Registers,
+    /// The seed rows are hashed with
+    seed: u64,
+    /// The id of the key rows were hashed with, or merged sketches were
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub(crate) key_id: Option<u64>,
+}
+
+impl Default for SparseSketch {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+impl Counter for SparseSketch {
+    fn add<'a>(&mut self, v: impl Hash) -> Result<(), HMHError<'a>> {
+        let (idx, reg) = register_update(hash(v, self.seed));
+        self.set(idx, reg)
+    }
+}
+
+impl SparseSketch {
+    /// An empty sketch, it's memory charged to the given budget and rows hashed
+    /// with the given key
+    pub(crate) fn new(budget: Option<Arc<Budget>>, key: Option<HashKey>) -> Self {
+        SparseSketch {
+            registers: Registers::Sparse(Buf::new(budget)),
+            seed: key.map_or(0, |k| k.seed),
+            key_id: key.map(|k| k.id),
+        }
+    }
+
+    /// Raise the register to the given value, if that is larger
+    fn set<'a>(&mut self, idx: usize, reg: u16) -> Result<(), HMHError<'a>> {
+        match &mut self.registers {
+            Registers::Dense(regs) => regs[idx] = regs[idx].max(reg),
+            Registers::Sparse(entries) => {
+                match entries.binary_search_by_key(&(idx as u16), |(i, _)| *i) {
+                    Ok(pos) => entries[pos].1 = entries[pos].1.max(reg),
+                    Err(pos) => entries.insert(pos, (idx as u16, reg))?,
+                }
+                self.densify_if_full()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn densify_if_full<'a>(&mut self) -> Result<(), HMHError<'a>> {
+        if let Registers::Sparse(entries) = &self.registers {
+            if entries.len() > SPARSE_MAX {
+                let mut regs = Buf::zeroed(REGISTERS, entries.budget())?;
+                for (idx, reg) in entries.iter() {
+                    regs[usize::from(*idx)] = *reg;
+                }
+                self.registers = Registers::Dense(regs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge all registers, given in order, into this sketch
+    #[cfg_attr(not(feature = "serialize"), allow(dead_code))]
+    pub(crate) fn union<'a>(
+        &mut self,
+        other: impl Iterator<Item = u16>,
+    ) -> Result<(), HMHError<'a>> {
+        match &mut self.registers {
+            Registers::Dense(regs) => {
+                for (r, rr) in regs.iter_mut().zip(other) {
+                    *r = (*r).max(rr);
+                }
+            }
+            Registers::Sparse(entries) => {
+                // Both are ordered by index
+                let mut merged = Buf::new(entries.budget());
+                let mut ours = entries.iter().copied().peekable();
+                for (idx, rr) in other.enumerate() {
+                    let r = ours
+                        .next_if(|(i, _)| usize::from(*i) == idx)
+                        .map_or(0, |(_, r)| r);
+                    if r != 0 || rr != 0 {
+                        merged.push((idx as u16, r.max(rr)))?;
+                    }
+                }
+                *entries = merged;
+                self.densify_if_full()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// All registers, in order
+    pub(crate) fn registers(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match &self.registers {
+            Registers::Dense(regs) => Box::new(regs.iter().copied()),
+            Registers::Sparse(entries) => {
+                let mut entries = entries.iter().peekable();
+                Box::new((0..REGISTERS).map(move |idx| {
+                    entries
+                        .next_if(|(i, _)| usize::from(*i) == idx)
+                        .map_or(0, |(_, reg)| *reg)
+                }))
+            }
+        }
+    }
+
+    pub(crate) fn cardinality(&self) -> f64 {
+        let mut estimate = Estimate::default();
+        self.registers().for_each(|reg| estimate.add(reg));
+        estimate.cardinality()
+    }
+}
+
+/// The running sums of the cardinality-estimate over all registers
+#[derive(Default)]
+pub(crate) struct Estimate {
+    sum: f64,
+    zeros: f64,
+}
+
+impl Estimate {
+    pub(crate) fn add(&mut self, reg: u16) {
+        let lz = reg >> LZ_SHIFT;
+        if lz == 0 {
+            self.zeros += 1.0;
+        }
+        self.sum += 1.0 / 2f64.powi(i32::from(lz));
+    }
+
+    pub(crate) fn cardinality(&self) -> f64 {
+        let m = REGISTERS as f64;
+        ALPHA * m * (m - self.zeros) / (beta(self.zeros) + self.sum)
+    }
+}
+
+fn beta(ez: f64) -> f64 {
+    let zl = (ez + 1.0).ln();
+    -0.370_393_911 * ez
+        + 0.070_471_823 * zl
+        + 0.173_936_86 * zl.powi(2)
+        + 0.163_398_39 * zl.powi(3)
+        + -0.092_377_45 * zl.powi(4)
+        + 0.037_380_27 * zl.powi(5)
+        + -0.005_384_159 * zl.powi(6)
+        + 0.000_424_19 * zl.powi(7)
+}
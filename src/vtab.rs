@@ -0,0 +1,278 @@
+//! Eponymous virtual tables, used as table-valued functions
+//!
+//! All tables share a single `sqlite3_module`, defined in shim.c, whose methods are
+//! implemented here. Each table is registered with a `TableDef` as it's client data.
+use std::{ffi, mem, os::raw, slice};
+
+use super::bindings::*;
+use super::{catch_panic, set_text_result, HMHError};
+
+/// A single value in a row
+pub(crate) enum Cell {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+pub(crate) type Rows = Vec<Vec<Cell>>;
+
+/// Describes a table-valued function
+pub(crate) struct TableDef {
+    /// The name of the module in shim.c, to name the function in errors
+    pub name: &'static str,
+    /// The `CREATE TABLE`-statement given to `sqlite3_declare_vtab`.
+    ///
+    /// The function's arguments are the trailing `HIDDEN` columns.
+    pub schema: &'static str,
+    /// Total number of columns, including the hidden ones
+    pub columns: usize,
+    /// Number of hidden columns, the first `required` of which are mandatory
+    pub args: usize,
+    pub required: usize,
+    /// Computes all rows, given the arguments
+    pub rows: for<'a> unsafe fn(
+        *mut sqlite3,
+        &'a [Option<*mut sqlite3_value>],
+    ) -> Result<Rows, HMHError<'a>>,
+}
+
+impl TableDef {
+    fn first_arg(&self) -> usize {
+        self.columns - self.args
+    }
+}
+
+#[repr(C)]
+struct VTab {
+    base: sqlite3_vtab,
+    db: *mut sqlite3,
+    def: &'static TableDef,
+}
+
+#[repr(C)]
+struct Cursor {
+    base: sqlite3_vtab_cursor,
+    rows: Rows,
+    pos: usize,
+}
+
+unsafe fn set_vtab_error(vtab: *mut sqlite3_vtab, msg: &str) {
+    sqlite3_free((*vtab).zErrMsg as *mut ffi::c_void);
+    let msg = format!("{}(): {}", (*(vtab as *mut VTab)).def.name, msg);
+    let msg = ffi::CString::new(msg.replace('\0', "")).unwrap_or_default();
+    (*vtab).zErrMsg = sqlite3_mprintf(b"%s\0".as_ptr() as *const raw::c_char, msg.as_ptr());
+}
+
+/// Run a method of the module, turning a panic into an error on the table
+unsafe fn guard(vtab: *mut sqlite3_vtab, f: impl FnOnce() -> raw::c_int) -> raw::c_int {
+    match catch_panic(|| Ok(f())) {
+        Ok(rc) => rc,
+        Err(e) => {
+            set_vtab_error(vtab, &e.to_string());
+            e.code() as raw::c_int
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_connect(
+    db: *mut sqlite3,
+    aux: *mut ffi::c_void,
+    _argc: raw::c_int,
+    _argv: *const *const raw::c_char,
+    vtab: *mut *mut sqlite3_vtab,
+    err: *mut *mut raw::c_char,
+) -> raw::c_int {
+    let def = &*(aux as *const TableDef);
+    let schema = match ffi::CString::new(def.schema) {
+        Ok(schema) => schema,
+        Err(_) => {
+            *err = sqlite3_mprintf(b"schema contains NUL\0".as_ptr() as *const raw::c_char);
+            return SQLITE_ERROR as raw::c_int;
+        }
+    };
+    let rc = sqlite3_declare_vtab(db, schema.as_ptr());
+    if rc != SQLITE_OK as raw::c_int {
+        return rc;
+    }
+    let table = Box::new(VTab {
+        base: mem::zeroed(),
+        db,
+        def,
+    });
+    *vtab = Box::into_raw(table) as *mut sqlite3_vtab;
+    SQLITE_OK as raw::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_disconnect(vtab: *mut sqlite3_vtab) -> raw::c_int {
+    sqlite3_free((*vtab).zErrMsg as *mut ffi::c_void);
+    drop(Box::from_raw(vtab as *mut VTab));
+    SQLITE_OK as raw::c_int
+}
+
+/// Arguments are passed to xFilter in column-order; `idxNum` is the bitmask of
+/// arguments present.
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_best_index(
+    vtab: *mut sqlite3_vtab,
+    info: *mut sqlite3_index_info,
+) -> raw::c_int {
+    guard(vtab, || best_index(vtab, &mut *info))
+}
+
+unsafe fn best_index(vtab: *mut sqlite3_vtab, info: &mut sqlite3_index_info) -> raw::c_int {
+    let def = (*(vtab as *mut VTab)).def;
+    let constraints = slice::from_raw_parts(info.aConstraint, info.nConstraint as usize);
+    let usage = slice::from_raw_parts_mut(info.aConstraintUsage, info.nConstraint as usize);
+    let mut arg_constraint = vec![None; def.args];
+    let mut unusable = 0;
+    for (i, c) in constraints.iter().enumerate() {
+        let col = c.iColumn as usize;
+        if c.iColumn < 0 || col < def.first_arg() || c.op as u32 != SQLITE_INDEX_CONSTRAINT_EQ {
+            continue;
+        }
+        if c.usable == 0 {
+            unusable |= 1 << (col - def.first_arg());
+        } else {
+            arg_constraint[col - def.first_arg()] = Some(i);
+        }
+    }
+    let mut idx_num = 0;
+    let mut argv_index = 0;
+    for (arg, c) in arg_constraint.iter().enumerate() {
+        if let Some(i) = c {
+            argv_index += 1;
+            idx_num |= 1 << arg;
+            usage[*i].argvIndex = argv_index;
+            usage[*i].omit = 1;
+        }
+    }
+    if unusable & !idx_num != 0 {
+        // Try again with the argument made available
+        return SQLITE_CONSTRAINT as raw::c_int;
+    }
+    info.idxNum = idx_num;
+    info.estimatedCost = 1000.0;
+    SQLITE_OK as raw::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_open(
+    _vtab: *mut sqlite3_vtab,
+    cursor: *mut *mut sqlite3_vtab_cursor,
+) -> raw::c_int {
+    let cur = Box::new(Cursor {
+        base: mem::zeroed(),
+        rows: Vec::new(),
+        pos: 0,
+    });
+    *cursor = Box::into_raw(cur) as *mut sqlite3_vtab_cursor;
+    SQLITE_OK as raw::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_close(cursor: *mut sqlite3_vtab_cursor) -> raw::c_int {
+    drop(Box::from_raw(cursor as *mut Cursor));
+    SQLITE_OK as raw::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_filter(
+    cursor: *mut sqlite3_vtab_cursor,
+    idx_num: raw::c_int,
+    _idx_str: *const raw::c_char,
+    argc: raw::c_int,
+    argv: *mut *mut sqlite3_value,
+) -> raw::c_int {
+    guard((*cursor).pVtab, || filter(cursor, idx_num, argc, argv))
+}
+
+unsafe fn filter(
+    cursor: *mut sqlite3_vtab_cursor,
+    idx_num: raw::c_int,
+    argc: raw::c_int,
+    argv: *mut *mut sqlite3_value,
+) -> raw::c_int {
+    let cur = &mut *(cursor as *mut Cursor);
+    let vtab = &*(cur.base.pVtab as *mut VTab);
+    let mut given = if argc > 0 {
+        slice::from_raw_parts(argv, argc as usize)
+    } else {
+        &[]
+    }
+    .iter();
+    let args = (0..vtab.def.args)
+        .map(|arg| {
+            if idx_num & (1 << arg) != 0 {
+                given.next().copied()
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    cur.rows.clear();
+    cur.pos = 0;
+    if args[..vtab.def.required].iter().any(Option::is_none) {
+        set_vtab_error(cur.base.pVtab, "missing argument to table-valued function");
+        return SQLITE_ERROR as raw::c_int;
+    }
+    match (vtab.def.rows)(vtab.db, &args) {
+        Ok(rows) => {
+            cur.rows = rows;
+            SQLITE_OK as raw::c_int
+        }
+        Err(e) => {
+            set_vtab_error(cur.base.pVtab, &e.to_string());
+            e.code() as raw::c_int
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_next(cursor: *mut sqlite3_vtab_cursor) -> raw::c_int {
+    (*(cursor as *mut Cursor)).pos += 1;
+    SQLITE_OK as raw::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_eof(cursor: *mut sqlite3_vtab_cursor) -> raw::c_int {
+    let cur = &*(cursor as *mut Cursor);
+    (cur.pos >= cur.rows.len()) as raw::c_int
+}
+
+/// The hidden argument-columns read as NULL
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_column(
+    cursor: *mut sqlite3_vtab_cursor,
+    ctx: *mut sqlite3_context,
+    col: raw::c_int,
+) -> raw::c_int {
+    let cur = &*(cursor as *mut Cursor);
+    // sqlite picks up an error set on the context
+    let name = (*(cur.base.pVtab as *mut VTab)).def.name;
+    HMHError::set_ctx_named(ctx, name, || {
+        let row = cur
+            .rows
+            .get(cur.pos)
+            .ok_or(HMHError::InvalidArgument("cursor is past the last row"))?;
+        match row.get(col as usize).unwrap_or(&Cell::Null) {
+            Cell::Null => sqlite3_result_null(ctx),
+            Cell::Int(i) => sqlite3_result_int64(ctx, *i),
+            Cell::Float(f) => sqlite3_result_double(ctx, *f),
+            Cell::Text(s) => set_text_result(ctx, s),
+        }
+        Ok(())
+    });
+    SQLITE_OK as raw::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hyperminhash_vtab_rowid(
+    cursor: *mut sqlite3_vtab_cursor,
+    rowid: *mut sqlite3_int64,
+) -> raw::c_int {
+    *rowid = (*(cursor as *mut Cursor)).pos as sqlite3_int64;
+    SQLITE_OK as raw::c_int
+}
@@ -25,6 +25,29 @@ fn empty_table() -> rusqlite::Result<()> {
     Ok(())
 }
 
+#[test]
+fn filtered_groups() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+    con.execute(
+        "INSERT INTO foo (id) VALUES (1), (2), (3)",
+        rusqlite::params![],
+    )?;
+    // The step-function is never called for any group
+    let (count, columns, grouped): (f64, String, String) = con.query_row(
+        "SELECT hyperminhash(id) FILTER (WHERE id > 5),
+                hyperminhash_columns(id) FILTER (WHERE id > 5),
+                hyperminhash_grouped(id, id) FILTER (WHERE id > 5)
+         FROM foo",
+        rusqlite::params![],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    assert_eq!(count, 0.0);
+    assert_eq!(columns, "{}");
+    assert_eq!(grouped, "{}");
+    Ok(())
+}
+
 #[test]
 fn simple_count_error() -> rusqlite::Result<()> {
     let con = init_db()?;
@@ -165,3 +188,722 @@ fn null_data() -> rusqlite::Result<()> {
     assert!((1.0 - (r / real_count)).abs() < 0.05);
     Ok(())
 }
+
+fn columns(con: &rusqlite::Connection, expr: &str) -> rusqlite::Result<serde_json::Value> {
+    let s: String = con.query_row(expr, rusqlite::params![], |row| row.get(0))?;
+    Ok(serde_json::from_str(&s).unwrap())
+}
+
+#[test]
+fn columns_count() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute(
+        "CREATE TABLE foobar (foo INT, bar INT, baz TEXT)",
+        rusqlite::params![],
+    )?;
+    let mut stmt = con.prepare("INSERT INTO foobar (foo, bar, baz) VALUES (?1, ?2, ?3)")?;
+    for i in 0..1000 {
+        stmt.execute(rusqlite::params![i, i % 10, None::<String>])?;
+    }
+
+    let counts = columns(
+        &con,
+        "SELECT hyperminhash_columns(foo, bar, baz) FROM foobar",
+    )?;
+    let counts = counts.as_object().unwrap();
+    assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["0", "1", "2"]);
+    // Each column is counted on it's own, with NULLs ignored as COUNT(DISTINCT) does
+    for (col, expected) in &[("0", 1000.0), ("1", 10.0)] {
+        let r = counts[*col].as_f64().unwrap();
+        assert!((1.0 - (r / expected)).abs() < 0.05);
+    }
+    assert_eq!(counts["2"], 0.0);
+
+    // Agrees with counting the column on it's own
+    let foo: f64 = con.query_row(
+        "SELECT hyperminhash(foo) FROM foobar",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert!((counts["0"].as_f64().unwrap() - foo).abs() < 1e-9);
+    Ok(())
+}
+
+#[test]
+fn rows_hash_as_tuples() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (v)", rusqlite::params![])?;
+    let mut stmt = con.prepare("INSERT INTO foo (v) VALUES (?1)")?;
+    for i in 0..2000 {
+        match i % 4 {
+            0 => stmt.execute(rusqlite::params![i])?,
+            1 => stmt.execute(rusqlite::params![i as f64 / 3.0])?,
+            2 => stmt.execute(rusqlite::params![i.to_string()])?,
+            _ => stmt.execute(rusqlite::params![i.to_string().into_bytes()])?,
+        };
+    }
+    // HYPERMINHASH_COLUMNS() hashes a tuple of one value, HYPERMINHASH() hashes the
+    // row's values as they come
+    let counts = columns(&con, "SELECT hyperminhash_columns(v) FROM foo")?;
+    let r: f64 = con.query_row(
+        "SELECT hyperminhash(v) FROM foo",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert!((counts["0"].as_f64().unwrap() - r).abs() < 1e-9);
+    Ok(())
+}
+
+#[test]
+fn columns_empty_table() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+    let counts = columns(&con, "SELECT hyperminhash_columns(id) FROM foo")?;
+    assert_eq!(counts, serde_json::json!({}));
+    Ok(())
+}
+
+#[test]
+fn profile() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute(
+        r#"CREATE TABLE "odd ""name""" (id INT, mixed, empty TEXT)"#,
+        rusqlite::params![],
+    )?;
+    let mut stmt = con.prepare(r#"INSERT INTO "odd ""name""" VALUES (?1, ?2, NULL)"#)?;
+    for i in 0..1000 {
+        let mixed = match i % 4 {
+            0 => rusqlite::types::Value::Integer(i % 10),
+            1 => rusqlite::types::Value::Real(0.5),
+            2 => rusqlite::types::Value::Text("foo".to_owned()),
+            _ => rusqlite::types::Value::Null,
+        };
+        stmt.execute(rusqlite::params![i, mixed])?;
+    }
+
+    let mut stmt = con.prepare(
+        r#"SELECT name, distinct_count, std_error, null_count, integer_count, real_count, text_count, blob_count
+           FROM hyperminhash_profile('odd "name"')"#,
+    )?;
+    let profile = stmt
+        .query_map(rusqlite::params![], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, f64>(2)?,
+                [
+                    row.get::<_, i64>(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                ],
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    assert_eq!(
+        profile.iter().map(|p| p.0.as_str()).collect::<Vec<_>>(),
+        vec!["id", "mixed", "empty"]
+    );
+    let (_, distinct, std_error, classes) = &profile[0];
+    assert!((1.0 - (distinct / 1000.0)).abs() < 0.05);
+    assert!(*std_error > 0.0 && *std_error < distinct * 0.05);
+    assert_eq!(classes, &[0, 1000, 0, 0, 0]);
+    // Even integers modulo 10, one real, one text
+    let (_, distinct, _, classes) = &profile[1];
+    assert!((1.0 - (distinct / 7.0)).abs() < 0.05);
+    assert_eq!(classes, &[250, 250, 250, 250, 0]);
+    let (_, distinct, _, classes) = &profile[2];
+    assert_eq!(*distinct, 0.0);
+    assert_eq!(classes, &[1000, 0, 0, 0, 0]);
+    Ok(())
+}
+
+#[test]
+fn profile_no_such_table() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    let r: rusqlite::Result<f64> = con.query_row(
+        "SELECT distinct_count FROM hyperminhash_profile('foo')",
+        rusqlite::params![],
+        |row| row.get(0),
+    );
+    match r {
+        Err(e) if e.to_string().contains("no such table") => Ok(()),
+        other => panic!("did not complain about table: {:?}", other),
+    }
+}
+
+/// lhs, rhs, is_key, is_dependency
+type Dependency = (String, Option<String>, bool, Option<bool>);
+
+fn dependencies(con: &rusqlite::Connection, args: &str) -> rusqlite::Result<Vec<Dependency>> {
+    let mut stmt = con.prepare(&format!(
+        "SELECT lhs, rhs, is_key, is_dependency FROM hyperminhash_dependencies({})",
+        args
+    ))?;
+    let rows = stmt
+        .query_map(rusqlite::params![], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect();
+    rows
+}
+
+#[test]
+fn dependencies_pairs() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute(
+        "CREATE TABLE foo (id INT, city INT, country INT)",
+        rusqlite::params![],
+    )?;
+    let mut stmt = con.prepare("INSERT INTO foo (id, city, country) VALUES (?1, ?2, ?3)")?;
+    for i in 0..1000 {
+        stmt.execute([i, i % 100, i % 10])?;
+    }
+
+    let deps = dependencies(&con, "'foo'")?;
+    // Three columns on their own and each with two others
+    assert_eq!(deps.len(), 9);
+    let find = |lhs: &str, rhs: Option<&str>| {
+        deps.iter()
+            .find(|d| d.0 == lhs && d.1.as_deref() == rhs)
+            .unwrap()
+            .clone()
+    };
+    assert!(find("id", None).2);
+    assert!(!find("city", None).2);
+    assert_eq!(find("id", Some("city")).3, Some(true));
+    assert_eq!(find("city", Some("country")).3, Some(true));
+    assert_eq!(find("country", Some("city")).3, Some(false));
+    assert_eq!(find("city", None).3, None);
+    Ok(())
+}
+
+#[test]
+fn dependencies_sets() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute(
+        "CREATE TABLE foo (a INT, b INT, c INT)",
+        rusqlite::params![],
+    )?;
+    let mut stmt = con.prepare("INSERT INTO foo (a, b, c) VALUES (?1, ?2, ?3)")?;
+    for i in 0..1000 {
+        stmt.execute([i % 40, i / 40, i % 7])?;
+    }
+    // Neither a nor b are keys, but together they are
+    let deps = dependencies(&con, "'foo', 2")?;
+    let keys = deps
+        .iter()
+        .filter(|d| d.1.is_none() && d.2)
+        .map(|d| d.0.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, vec!["a, b"]);
+    assert!(deps
+        .iter()
+        .any(|d| d.0 == "a, b" && d.1.as_deref() == Some("c") && d.3 == Some(true)));
+
+    let r = dependencies(&con, "'foo', 0");
+    match r {
+        Err(e) if e.to_string().contains("must be positive") => Ok(()),
+        other => panic!("did not complain about max_columns: {:?}", other),
+    }
+}
+
+fn stat1(con: &rusqlite::Connection) -> rusqlite::Result<Stat1> {
+    let mut stmt =
+        con.prepare("SELECT idx, stat FROM sqlite_stat1 WHERE tbl = 'foo' ORDER BY idx")?;
+    let rows = stmt
+        .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect();
+    rows
+}
+
+type Stat1 = Vec<(Option<String>, String)>;
+
+fn assert_stats_close(stats: &Stat1, expected: &Stat1) {
+    let parse = |stat: &str| {
+        stat.split(' ')
+            .map(|n| n.parse::<f64>().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(stats.len(), expected.len(), "{:?} {:?}", stats, expected);
+    for ((idx, stat), (expected_idx, expected_stat)) in stats.iter().zip(expected) {
+        assert_eq!(idx, expected_idx);
+        let (stat, expected_stat) = (parse(stat), parse(expected_stat));
+        assert_eq!(stat.len(), expected_stat.len());
+        for (n, expected_n) in stat.iter().zip(&expected_stat) {
+            assert!((1.0 - (n / expected_n)).abs() < 0.05, "{:?}", stats);
+        }
+    }
+}
+
+#[test]
+fn analyze() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute_batch(
+        r#"CREATE TABLE foo (a INT, b INT, c INT);
+           CREATE INDEX foo_ab ON foo (a, b);
+           CREATE INDEX foo_b ON foo (b);
+           CREATE UNIQUE INDEX foo_c ON foo (c);
+           CREATE INDEX foo_partial ON foo (a) WHERE b > 1;
+           CREATE INDEX foo_expr ON foo (a + b);"#,
+    )?;
+    let mut stmt = con.prepare("INSERT INTO foo (a, b, c) VALUES (?1, ?2, ?3)")?;
+    for i in 0..1000 {
+        stmt.execute([i % 10, i % 50, i])?;
+    }
+
+    // Agrees with what ANALYZE computes
+    con.execute("ANALYZE foo", rusqlite::params![])?;
+    let expected = stat1(&con)?
+        .into_iter()
+        .filter(|(idx, _)| !matches!(idx.as_deref(), Some("foo_partial" | "foo_expr")))
+        .collect::<Vec<_>>();
+    con.execute("DELETE FROM sqlite_stat1", rusqlite::params![])?;
+    let written: i64 = con.query_row(
+        "SELECT hyperminhash_analyze('foo')",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert_eq!(written, 3);
+    assert_stats_close(&stat1(&con)?, &expected);
+
+    // Previous statistics are replaced
+    con.query_row(
+        "SELECT hyperminhash_analyze('foo')",
+        rusqlite::params![],
+        |row| row.get::<_, i64>(0),
+    )?;
+    assert_stats_close(&stat1(&con)?, &expected);
+    Ok(())
+}
+
+#[test]
+fn analyze_without_index() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (a INT)", rusqlite::params![])?;
+    con.execute(
+        "INSERT INTO foo (a) VALUES (1), (2), (3)",
+        rusqlite::params![],
+    )?;
+    con.query_row(
+        "SELECT hyperminhash_analyze('foo')",
+        rusqlite::params![],
+        |row| row.get::<_, i64>(0),
+    )?;
+    assert_eq!(stat1(&con)?, vec![(None, "3".to_owned())]);
+    Ok(())
+}
+
+#[test]
+fn grouped() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute(
+        "CREATE TABLE visits (day INT, country TEXT, user INT)",
+        rusqlite::params![],
+    )?;
+    let mut stmt = con.prepare("INSERT INTO visits (day, country, user) VALUES (?1, ?2, ?3)")?;
+    for i in 0..2000 {
+        let country = match i % 3 {
+            0 => Some("de"),
+            1 => Some("fr"),
+            _ => None,
+        };
+        stmt.execute(rusqlite::params![i % 2, country, i % 600])?;
+    }
+
+    let mut stmt = con.prepare(
+        "SELECT day, hyperminhash_grouped(country, user) FROM visits GROUP BY day ORDER BY day",
+    )?;
+    let days = stmt
+        .query_map(rusqlite::params![], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    assert_eq!(days.len(), 2);
+    for (day, counts) in days {
+        let counts: serde_json::Value = serde_json::from_str(&counts).unwrap();
+        // Rows with a NULL-key are not counted
+        assert_eq!(
+            counts.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["de", "fr"]
+        );
+        for country in &["de", "fr"] {
+            let expected: f64 = con.query_row(
+                "SELECT COUNT(DISTINCT user) FROM visits WHERE day = ?1 AND country = ?2",
+                rusqlite::params![day, country],
+                |row| row.get(0),
+            )?;
+            let r = counts[country].as_f64().unwrap();
+            assert!((1.0 - (r / expected)).abs() < 0.05);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn many_small_groups() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (k INT, v INT)", rusqlite::params![])?;
+    let mut stmt = con.prepare("INSERT INTO foo (k, v) VALUES (?1, ?2)")?;
+    // Many groups small enough to stay sparse, a few which have to become dense
+    for k in 0..3000 {
+        for v in 0..(k % 3) {
+            stmt.execute([k, v])?;
+        }
+    }
+    for (k, n) in &[(-1, 3000), (-2, 5000), (-3, 20000)] {
+        for v in 0..*n {
+            stmt.execute([*k, v])?;
+        }
+    }
+
+    let mut stmt = con.prepare(
+        r#"SELECT k, COUNT(DISTINCT v), HYPERMINHASH(v), HYPERMINHASH_GROUPED(1, v)
+           FROM foo GROUP BY k"#,
+    )?;
+    let mut rows = stmt.query(rusqlite::params![])?;
+    let mut groups = 0;
+    while let Some(row) = rows.next()? {
+        let (k, expected, r): (i64, f64, f64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        // HYPERMINHASH_GROUPED() counts in a dense sketch
+        let dense: serde_json::Value = serde_json::from_str(&row.get::<_, String>(3)?).unwrap();
+        let dense = dense["1"].as_f64().unwrap();
+        assert!(
+            (r - dense).abs() <= 1e-9 * dense,
+            "{}: {} vs {}",
+            k,
+            r,
+            dense
+        );
+        assert!((r - expected).abs() <= 0.05 * expected, "{}", k);
+        groups += 1;
+    }
+    assert_eq!(groups, 2000 + 3);
+    Ok(())
+}
+
+#[test]
+fn grouped_keys() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    // Keys are ordered as sqlite orders them; INTEGERs and REALs of the same value
+    // are the same key
+    for (keys, expected) in &[
+        (
+            "SELECT 10 AS key UNION ALL SELECT NULL UNION ALL SELECT 9 UNION ALL SELECT -7",
+            &["-7", "9", "10"][..],
+        ),
+        (
+            "SELECT 2.5 AS key UNION ALL SELECT 2 UNION ALL SELECT 2.0 UNION ALL SELECT -0.5",
+            &["-0.5", "2", "2.5"][..],
+        ),
+        (
+            "SELECT 'b' AS key UNION ALL SELECT 'B' UNION ALL SELECT 'a'",
+            &["B", "a", "b"][..],
+        ),
+        (
+            "SELECT X'0AFF' AS key UNION ALL SELECT X'01'",
+            &["01", "0AFF"][..],
+        ),
+    ] {
+        let counts: String = con.query_row(
+            &format!("SELECT hyperminhash_grouped(key, 1) FROM ({})", keys),
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        let counts: serde_json::Value = serde_json::from_str(&counts).unwrap();
+        assert_eq!(
+            &counts.as_object().unwrap().keys().collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    // There is no cap on the number of keys, only the memory-limit
+    let keys = "WITH RECURSIVE k(key) AS (SELECT 0 UNION ALL SELECT key + 1 FROM k WHERE key < 5000) SELECT key FROM k";
+    let counts: String = con.query_row(
+        &format!("SELECT hyperminhash_grouped(key, key) FROM ({})", keys),
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    let counts: serde_json::Value = serde_json::from_str(&counts).unwrap();
+    assert_eq!(counts.as_object().unwrap().len(), 5001);
+    con.query_row("SELECT hyperminhash_memory_limit(20000)", [], |_| Ok(()))?;
+
+    // Keys of different types would collide as JSON-keys
+    for (keys, needle) in &[
+        (
+            "SELECT 1 AS key UNION ALL SELECT '1'",
+            "all numbers, all TEXT or all BLOBs",
+        ),
+        (
+            "SELECT 'AB' AS key UNION ALL SELECT X'AB'",
+            "all numbers, all TEXT or all BLOBs",
+        ),
+        (keys, "out of memory"),
+    ] {
+        let r: rusqlite::Result<String> = con.query_row(
+            &format!("SELECT hyperminhash_grouped(key, 1) FROM ({})", keys),
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        match r {
+            Err(e) if e.to_string().contains(needle) => {}
+            other => panic!("did not complain about keys {}: {:?}", keys, other),
+        }
+    }
+    let r: rusqlite::Result<String> = con.query_row(
+        "SELECT hyperminhash_grouped()",
+        rusqlite::params![],
+        |row| row.get(0),
+    );
+    match r {
+        Err(e) if e.to_string().contains("at least 1 argument") => Ok(()),
+        other => panic!("did not complain about arguments: {:?}", other),
+    }
+}
+
+#[test]
+fn json_elements() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (id INT, doc TEXT)", rusqlite::params![])?;
+    let mut stmt = con.prepare("INSERT INTO foo (id, doc) VALUES (?1, ?2)")?;
+    for i in 0..500 {
+        let doc = serde_json::json!({"tags": [i, i + 1, format!("t{}", i % 10), null]});
+        stmt.execute(rusqlite::params![i, doc.to_string()])?;
+    }
+    stmt.execute(rusqlite::params![500, None::<String>])?;
+
+    let r: f64 = con.query_row(
+        "SELECT hyperminhash_json(doc, '$.tags') FROM foo",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    // 0..=500 and t0..t9
+    assert!((1.0 - (r / 511.0)).abs() < 0.05);
+
+    // Elements are counted as the values themselves would be
+    let ids: f64 = con.query_row(
+        "SELECT hyperminhash(id) FROM foo WHERE doc IS NOT NULL",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    let first_tags: f64 = con.query_row(
+        "SELECT hyperminhash_json(doc, '$.tags[0]') FROM foo",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert_eq!(ids, first_tags);
+    let whole: f64 = con.query_row(
+        "SELECT hyperminhash_json('[1, 2, 3]')",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert!((whole - 3.0).abs() < 0.1);
+    Ok(())
+}
+
+#[test]
+fn json_objects_are_canonical() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    let r: f64 = con.query_row(
+        r#"SELECT hyperminhash_json(doc) FROM (SELECT '[{"a": 1, "b": [{"x": 1, "y": 2}]}]' AS doc UNION ALL SELECT '[{"b": [{"y": 2, "x": 1}], "a": 1}]')"#,
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert!((r - 1.0).abs() < 0.1);
+    Ok(())
+}
+
+#[test]
+fn json_bad_input() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    for (query, needle) in &[
+        ("SELECT hyperminhash_json('[1, 2')", "invalid JSON"),
+        (
+            "SELECT hyperminhash_json('[1, 2]', 'tags')",
+            "malformed JSON path",
+        ),
+        ("SELECT hyperminhash_json(X'00')", "expected JSON-text"),
+    ] {
+        let r: rusqlite::Result<f64> = con.query_row(query, rusqlite::params![], |row| row.get(0));
+        match r {
+            Err(e) if e.to_string().contains(needle) => {}
+            other => panic!("did not complain about {}: {:?}", query, other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn error_messages() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    for (query, msg) in &[
+        (
+            "SELECT hyperminhash_json(zeroblob(100000))",
+            "hyperminhash_json(): argument 1: expected JSON-text, found BLOB of 100000 bytes",
+        ),
+        (
+            "SELECT hyperminhash_json('[]', 5)",
+            "hyperminhash_json(): argument 2: expected a JSON-path, found INTEGER 5",
+        ),
+        (
+            "SELECT hyperminhash_memory_limit(-1)",
+            "hyperminhash_memory_limit(): argument 1: expected a non-negative INTEGER or NULL, found INTEGER -1",
+        ),
+    ] {
+        let r: rusqlite::Result<f64> = con.query_row(query, rusqlite::params![], |row| row.get(0));
+        match r {
+            Err(rusqlite::Error::SqliteFailure(e, Some(s))) => {
+                assert_eq!(e.code, rusqlite::ErrorCode::TypeMismatch);
+                assert_eq!(&s, msg);
+            }
+            other => panic!("did not complain about {}: {:?}", query, other),
+        }
+    }
+    Ok(())
+}
+
+fn memory_limit(con: &rusqlite::Connection, limit: Option<i64>) -> rusqlite::Result<Option<i64>> {
+    con.query_row(
+        "SELECT hyperminhash_memory_limit(?1)",
+        rusqlite::params![limit],
+        |row| row.get(0),
+    )
+}
+
+fn is_nomem(r: rusqlite::Result<f64>) -> bool {
+    matches!(r, Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::OutOfMemory)
+}
+
+#[test]
+fn memory_limit_setting() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    let current: Option<i64> = con.query_row(
+        "SELECT hyperminhash_memory_limit()",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert_eq!(current, None);
+    assert_eq!(memory_limit(&con, Some(100_000))?, None);
+    assert_eq!(memory_limit(&con, Some(200_000))?, Some(100_000));
+    assert_eq!(memory_limit(&con, None)?, Some(200_000));
+    assert_eq!(memory_limit(&con, Some(0))?, None);
+    match memory_limit(&con, Some(-1)) {
+        Err(e) if e.to_string().contains("non-negative") => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn memory_limit_covers_all_sketches() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute_batch(
+        "CREATE TABLE foo (id INT, s TEXT);
+         CREATE INDEX foo_id ON foo (id);
+         WITH RECURSIVE ids(id) AS (SELECT 0 UNION ALL SELECT id + 1 FROM ids WHERE id < 19999)
+         INSERT INTO foo (id, s) SELECT id, CAST(id AS TEXT) FROM ids;",
+    )?;
+    let queries = [
+        "SELECT hyperminhash_columns(id, s) FROM foo",
+        "SELECT hyperminhash_grouped(id % 2, id) FROM foo",
+        "SELECT COUNT(*) FROM hyperminhash_profile('foo')",
+        "SELECT COUNT(*) FROM hyperminhash_dependencies('foo')",
+        "SELECT hyperminhash_analyze('foo')",
+    ];
+    memory_limit(&con, Some(20_000))?;
+    for query in &queries {
+        match con.query_row(query, rusqlite::params![], |_| Ok(())) {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == rusqlite::ErrorCode::OutOfMemory => {}
+            other => panic!("{} exceeded the limit: {:?}", query, other),
+        }
+    }
+    memory_limit(&con, None)?;
+    for query in &queries {
+        con.query_row(query, rusqlite::params![], |_| Ok(()))?;
+    }
+    Ok(())
+}
+
+/// The sqlite-API handed to extensions, to load ours again by hand
+static API: std::sync::atomic::AtomicPtr<std::ffi::c_void> =
+    std::sync::atomic::AtomicPtr::new(std::ptr::null_mut());
+
+unsafe extern "C" fn capture_api(
+    _db: *mut std::ffi::c_void,
+    _err: *const std::ffi::c_void,
+    api: *const std::ffi::c_void,
+) -> i32 {
+    API.store(api as *mut _, std::sync::atomic::Ordering::SeqCst);
+    0
+}
+
+#[test]
+fn settings_survive_reloading() -> rusqlite::Result<()> {
+    init_db()?;
+    let ptr = capture_api
+        as unsafe extern "C" fn(
+            *mut std::ffi::c_void,
+            *const std::ffi::c_void,
+            *const std::ffi::c_void,
+        ) -> i32;
+    unsafe {
+        sqlite3_hyperminhash::testutil::sqlite3_auto_extension(Some(std::mem::transmute(ptr)));
+    }
+    let con = rusqlite::Connection::open_in_memory()?;
+    memory_limit(&con, Some(100_000))?;
+    let mut err: *mut std::os::raw::c_char = std::ptr::null_mut();
+    let rc = unsafe {
+        sqlite3_hyperminhash::sqlite3_sqlitehyperminhash_init(
+            con.handle() as *mut _,
+            &mut err as *mut _ as *const _,
+            API.load(std::sync::atomic::Ordering::SeqCst),
+        )
+    };
+    assert_eq!(rc, 0);
+    let current: Option<i64> = con.query_row(
+        "SELECT hyperminhash_memory_limit()",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert_eq!(current, Some(100_000));
+    // Other connections have their own settings
+    assert_eq!(memory_limit(&init_db()?, None)?, None);
+    Ok(())
+}
+
+#[test]
+fn memory_limit_per_connection() -> rusqlite::Result<()> {
+    let con = init_db()?;
+    con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+    let mut stmt = con.prepare("INSERT INTO foo (id) VALUES (?1)")?;
+    for i in 0..20000 {
+        stmt.execute([i])?;
+    }
+    let count = |sql: &str| con.query_row(sql, rusqlite::params![], |row| row.get::<_, f64>(0));
+
+    memory_limit(&con, Some(20_000))?;
+    // A small set fits, a large one does not
+    assert!(count("SELECT hyperminhash(id) FROM foo WHERE id < 2500").is_ok());
+    assert!(is_nomem(count("SELECT hyperminhash(id) FROM foo")));
+    // All sketches the connection holds at once share the budget
+    assert!(is_nomem(count(
+        "SELECT hyperminhash(id) + hyperminhash(-id) FROM foo WHERE id < 2500"
+    )));
+
+    // Other connections have budgets of their own
+    let other = init_db()?;
+    assert_eq!(memory_limit(&other, None)?, None);
+    let other_count: f64 = other.query_row(
+        "WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 19999)
+         SELECT hyperminhash(i) FROM n",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    assert!((1.0 - (other_count / 20000.0)).abs() < 0.05);
+
+    memory_limit(&con, None)?;
+    assert!((1.0 - (count("SELECT hyperminhash(id) FROM foo")? / 20000.0)).abs() < 0.05);
+    Ok(())
+}
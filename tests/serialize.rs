@@ -14,10 +14,28 @@ fn expect_error_msg<T: std::fmt::Debug>(
     }
 }
 
+/// The extended result-code of a failed query
+fn error_code<T>(r: &rusqlite::Result<T>) -> Option<std::os::raw::c_int> {
+    match r {
+        Err(rusqlite::Error::SqliteFailure(e, _)) => Some(e.extended_code),
+        _ => None,
+    }
+}
+
 #[cfg(feature = "serialize")]
 pub mod serialize {
     use super::*;
     use hyperminhash::Sketch;
+    #[cfg(feature = "compress")]
+    use rand::Rng;
+
+    /// A serialized sketch or timeline as it is before compression
+    fn inflated(buf: &[u8]) -> Vec<u8> {
+        match buf.strip_prefix(b"HMHZ") {
+            Some(deflated) => miniz_oxide::inflate::decompress_to_vec(deflated).unwrap(),
+            None => buf.to_vec(),
+        }
+    }
 
     macro_rules! test_wrong_type {
         ($name:ident, $func:literal) => {
@@ -28,7 +46,12 @@ pub mod serialize {
                     con.query_row(&format!("SELECT {}", $func), rusqlite::params![], |row| {
                         row.get(0)
                     });
-                expect_error_msg(r, "not of type BLOB", "did not complain about type:")
+                assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_MISMATCH));
+                expect_error_msg(
+                    r,
+                    "argument 1: expected a BLOB or TEXT",
+                    "did not complain about type:",
+                )
             }
         };
     }
@@ -42,10 +65,26 @@ pub mod serialize {
                     con.query_row(&format!("SELECT {}", $func), rusqlite::params![], |row| {
                         row.get(0)
                     });
+                assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+                expect_error_msg(r, "malformed value", "unpacked bad data without error")
+            }
+        };
+    }
+
+    macro_rules! test_bad_text {
+        ($name:ident, $func:literal) => {
+            #[test]
+            fn $name() -> rusqlite::Result<()> {
+                let con = init_db()?;
+                let r: rusqlite::Result<u8> =
+                    con.query_row(&format!("SELECT {}", $func), rusqlite::params![], |row| {
+                        row.get(0)
+                    });
+                assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
                 expect_error_msg(
                     r,
-                    "IO-error in hyperminhash",
-                    "unpacked bad data without error",
+                    "invalid text-encoded sketch",
+                    "decoded bad text without error",
                 )
             }
         };
@@ -59,7 +98,7 @@ pub mod serialize {
             con.query_row("SELECT HYPERMINHASH_ZERO()", rusqlite::params![], |row| {
                 row.get(0)
             })?;
-        let sketch = Sketch::load(&buf[..]).unwrap();
+        let sketch = Sketch::load(&inflated(&buf)[..]).unwrap();
         assert_eq!(sketch.cardinality(), 0.0);
         Ok(())
     }
@@ -75,7 +114,7 @@ pub mod serialize {
             rusqlite::params![],
             |row| row.get(0),
         )?;
-        let sketch = Sketch::load(&buf[..]).unwrap();
+        let sketch = Sketch::load(&inflated(&buf)[..]).unwrap();
         assert_eq!(sketch.cardinality(), 0.0);
 
         // Count is not zero
@@ -85,7 +124,7 @@ pub mod serialize {
             rusqlite::params![],
             |row| row.get(0),
         )?;
-        let r = Sketch::load(&buf[..]).unwrap().cardinality();
+        let r = Sketch::load(&inflated(&buf)[..]).unwrap().cardinality();
         assert!((1.0 - r).abs() < 0.05);
 
         Ok(())
@@ -114,7 +153,7 @@ pub mod serialize {
         Ok(())
     }
 
-    test_wrong_type!(deserialize_wrong_type, "HYPERMINHASH_DESERIALIZE('foo')");
+    test_wrong_type!(deserialize_wrong_type, "HYPERMINHASH_DESERIALIZE(1)");
     test_bad_data!(deserialize_bad_data, "HYPERMINHASH_DESERIALIZE(X'00')");
 
     #[test]
@@ -171,9 +210,158 @@ pub mod serialize {
         Ok(())
     }
 
-    test_wrong_type!(add_wrong_type, "HYPERMINHASH_ADD('foo')");
+    test_wrong_type!(add_wrong_type, "HYPERMINHASH_ADD(1)");
     test_bad_data!(add_bad_data, "HYPERMINHASH_ADD(X'00')");
 
+    #[test]
+    fn insert() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE foo (id INT, s TEXT)", rusqlite::params![])?;
+        con.execute(
+            "CREATE TABLE counts (id INT PRIMARY KEY, data BLOB)",
+            rusqlite::params![],
+        )?;
+        con.execute(
+            r#"CREATE TRIGGER count_foo AFTER INSERT ON foo
+               BEGIN
+                 INSERT INTO counts (id, data) VALUES (0, HYPERMINHASH_INSERT(NULL, NEW.id, NEW.s))
+                 ON CONFLICT (id) DO UPDATE SET data = HYPERMINHASH_INSERT(data, NEW.id, NEW.s);
+               END"#,
+            rusqlite::params![],
+        )?;
+        let mut stmt = con.prepare("INSERT INTO foo (id, s) VALUES (?1, ?2)")?;
+        for i in 0..200 {
+            stmt.execute(rusqlite::params![i % 100, Option::<&str>::None])?;
+        }
+        stmt.execute(rusqlite::params![0, "foo"])?;
+
+        // Values are added exactly as HYPERMINHASH_SERIALIZE() does
+        let same: bool = con.query_row(
+            "SELECT data = (SELECT HYPERMINHASH_SERIALIZE(id, s) FROM foo) FROM counts",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+        let r: f64 = con.query_row(
+            "SELECT HYPERMINHASH_DESERIALIZE(data) FROM counts",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!((1.0 - (r / 101.0)).abs() < 0.05);
+        Ok(())
+    }
+
+    #[test]
+    fn insert_no_arguments() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let r: rusqlite::Result<Vec<u8>> =
+            con.query_row("SELECT HYPERMINHASH_INSERT()", rusqlite::params![], |row| {
+                row.get(0)
+            });
+        expect_error_msg(r, "at least 1 argument", "accepted no arguments:")
+    }
+
+    test_wrong_type!(insert_wrong_type, "HYPERMINHASH_INSERT(1, 2)");
+
+    #[test]
+    fn insert_inplace() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE foo (id INT, s TEXT)", rusqlite::params![])?;
+        con.execute(
+            "CREATE TABLE counts (id INTEGER PRIMARY KEY, data BLOB)",
+            rusqlite::params![],
+        )?;
+        con.execute(
+            "INSERT INTO counts (id, data) VALUES (7, HYPERMINHASH_ZERO())",
+            rusqlite::params![],
+        )?;
+        con.execute(
+            r#"CREATE TRIGGER count_foo AFTER INSERT ON foo
+               BEGIN
+                 SELECT HYPERMINHASH_INSERT_INPLACE(NULL, 'counts', 'data', 7, NEW.id, NEW.s);
+               END"#,
+            rusqlite::params![],
+        )?;
+        let mut stmt = con.prepare("INSERT INTO foo (id, s) VALUES (?1, ?2)")?;
+        for i in 0..1000 {
+            stmt.execute(rusqlite::params![i % 500, (i % 3).to_string()])?;
+        }
+        stmt.execute(rusqlite::params![Option::<i64>::None, Option::<&str>::None])?;
+
+        // Registers are updated exactly as HYPERMINHASH_SERIALIZE() does; the
+        // compressed sketch was stored uncompressed on it's first update
+        let (data, expected): (Vec<u8>, Vec<u8>) = con.query_row(
+            "SELECT data, (SELECT HYPERMINHASH_SERIALIZE(id, s) FROM foo) FROM counts",
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(data, inflated(&expected));
+
+        // Adding a row a second time does not change the sketch
+        let changed: i64 = con.query_row(
+            "SELECT HYPERMINHASH_INSERT_INPLACE(NULL, 'counts', 'data', 7, 0, '0')",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(changed, 0);
+
+        // NULL stands for the main-schema, other schemas are given by name
+        con.execute_batch(
+            "ATTACH ':memory:' AS other;
+             CREATE TABLE other.counts (data BLOB);
+             INSERT INTO other.counts (rowid, data) VALUES (7, HYPERMINHASH_ZERO());",
+        )?;
+        let changed: i64 = con.query_row(
+            "SELECT HYPERMINHASH_INSERT_INPLACE('other', 'counts', 'data', 7, 0, '0')",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(changed, 1);
+        let (data, expected): (Vec<u8>, Vec<u8>) = con.query_row(
+            "SELECT data, (SELECT HYPERMINHASH_SERIALIZE(0, '0')) FROM other.counts",
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(data, inflated(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn insert_inplace_bad_target() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute(
+            "CREATE TABLE counts (id INTEGER PRIMARY KEY, data BLOB)",
+            rusqlite::params![],
+        )?;
+        con.execute(
+            "INSERT INTO counts (id, data) VALUES (1, HYPERMINHASH_ZERO()), (2, X'00')",
+            rusqlite::params![],
+        )?;
+        for (query, needle) in &[
+            ("NULL, 'counts', 'data', 3, 1", "blob-IO failed"),
+            ("NULL, 'counts', 'foo', 1, 1", "blob-IO failed"),
+            (
+                "NULL, 'counts', 'data', 2, 1",
+                "expected a serialized sketch",
+            ),
+            ("NULL, 'counts', 'data', '1'", "expected a rowid"),
+            ("'main', 'counts', 'data', 1.5, 1", "expected a rowid"),
+            ("'counts', 'data', 1, 1", "expected a column-name"),
+            ("NULL, 'counts', 'data'", "at least 4 argument"),
+            ("'temp', 'counts', 'data', 1, 1", "blob-IO failed"),
+            ("1, 'counts', 'data', 1, 1", "expected a schema-name"),
+        ] {
+            let r: rusqlite::Result<i64> = con.query_row(
+                &format!("SELECT HYPERMINHASH_INSERT_INPLACE({})", query),
+                rusqlite::params![],
+                |row| row.get(0),
+            );
+            expect_error_msg(r, needle, "did not complain about target:")?;
+        }
+        Ok(())
+    }
+    test_bad_data!(insert_bad_data, "HYPERMINHASH_INSERT(X'00', 1)");
+
     #[test]
     fn union() -> rusqlite::Result<()> {
         let con = init_db()?;
@@ -213,7 +401,91 @@ pub mod serialize {
         Ok(())
     }
 
-    test_wrong_type!(union_wrong_type, "HYPERMINHASH_UNION('foo')");
+    #[test]
+    fn views_match_sketches() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+        let mut stmt = con.prepare("INSERT INTO foo (id) VALUES (?1)")?;
+        for i in 0..20000 {
+            stmt.execute([i])?;
+        }
+        con.execute(
+            r#"CREATE TABLE stats AS
+               SELECT n, (SELECT HYPERMINHASH_SERIALIZE(id) FROM foo WHERE id < n) AS data
+               FROM (SELECT 0 AS n UNION ALL SELECT 10 UNION ALL SELECT 5000 UNION ALL SELECT 20000)"#,
+            rusqlite::params![],
+        )?;
+        // Unions are exact
+        let same: bool = con.query_row(
+            "SELECT (SELECT HYPERMINHASH_UNION(data) FROM stats)
+                    = (SELECT HYPERMINHASH_SERIALIZE(id) FROM foo)",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+        // Text-encodings are loaded as a `Sketch`, blobs are not
+        let mut stmt = con.prepare(
+            r#"SELECT a.n, b.n,
+                      HYPERMINHASH_DESERIALIZE(a.data),
+                      (SELECT HYPERMINHASH(id) FROM foo WHERE id < a.n),
+                      HYPERMINHASH_INTERSECTION(a.data, b.data),
+                      HYPERMINHASH_INTERSECTION(HYPERMINHASH_TO_TEXT(a.data), b.data)
+               FROM stats AS a, stats AS b"#,
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let (n, m): (i64, i64) = (row.get(0)?, row.get(1)?);
+            assert_eq!(row.get::<_, f64>(2)?, row.get::<_, f64>(3)?, "{}", n);
+            assert_eq!(row.get::<_, f64>(4)?, row.get::<_, f64>(5)?, "{}, {}", n, m);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn estimators_match_hyperminhash() -> rusqlite::Result<()> {
+        // Our register-updates and estimators are copies of `hyperminhash::Sketch`'s,
+        // so they have to agree with it bit for bit
+        let con = init_db()?;
+        con.execute_batch(
+            "CREATE TABLE foo (id INTEGER PRIMARY KEY);
+             WITH RECURSIVE ids(id) AS (SELECT 0 UNION ALL SELECT id + 1 FROM ids WHERE id < 199999)
+             INSERT INTO foo (id) SELECT id FROM ids;",
+        )?;
+        let sketch = |from: i64, to: i64| -> rusqlite::Result<(Vec<u8>, Vec<u8>, f64)> {
+            con.query_row(
+                "SELECT HYPERMINHASH_SERIALIZE(id), HYPERMINHASH_FROM_JSON(JSON_GROUP_ARRAY(id)),
+                        HYPERMINHASH(id)
+                 FROM foo WHERE id >= ?1 AND id < ?2",
+                [from, to],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+        };
+        for &n in &[0, 1, 10, 100, 1000, 4000, 5000, 20000, 100000] {
+            // Counted sparse, and dense by `hyperminhash::Sketch`
+            let (a, dense, sparse_estimate) = sketch(0, n)?;
+            assert_eq!(hex(&a), hex(&dense), "{}", n);
+            let reference = Sketch::load(&inflated(&a)[..]).unwrap();
+            let expected = reference.cardinality();
+            assert_eq!(sparse_estimate.to_bits(), expected.to_bits(), "{}", n);
+            let view_estimate: f64 =
+                con.query_row("SELECT HYPERMINHASH_DESERIALIZE(?1)", [&a], |row| {
+                    row.get(0)
+                })?;
+            assert_eq!(view_estimate.to_bits(), expected.to_bits(), "{}", n);
+
+            let (b, _, _) = sketch(n / 2, n / 2 + n)?;
+            let expected = reference.intersection(&Sketch::load(&inflated(&b)[..]).unwrap());
+            let view_intersection: f64 = con.query_row(
+                "SELECT HYPERMINHASH_INTERSECTION(?1, ?2)",
+                [&a, &b],
+                |row| row.get(0),
+            )?;
+            assert_eq!(view_intersection.to_bits(), expected.to_bits(), "{}", n);
+        }
+        Ok(())
+    }
+
+    test_wrong_type!(union_wrong_type, "HYPERMINHASH_UNION(1)");
     test_bad_data!(union_bad_data, "HYPERMINHASH_UNION(X'00')");
 
     #[test]
@@ -236,41 +508,1903 @@ pub mod serialize {
         Ok(())
     }
 
-    test_wrong_type!(
-        intersection_wrong_type,
-        "HYPERMINHASH_INTERSECTION('foo', 'bar')"
-    );
+    #[test]
+    fn constant_arguments() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+        let mut stmt = con.prepare("INSERT INTO foo (id) VALUES (?1)")?;
+        for i in 0..1000 {
+            stmt.execute([i])?;
+        }
+        con.execute(
+            r#"CREATE TABLE stats AS
+               SELECT n, (SELECT HYPERMINHASH_SERIALIZE(id) FROM foo WHERE id < n) AS data
+               FROM (SELECT 100 AS n UNION ALL SELECT 500 UNION ALL SELECT 900)"#,
+            rusqlite::params![],
+        )?;
+        con.execute(
+            "CREATE TABLE other AS SELECT HYPERMINHASH_SERIALIZE(id) AS data FROM foo WHERE id >= 250",
+            rusqlite::params![],
+        )?;
+        // The same computations, with the second argument once constant and once not;
+        // sqlite takes parameters to be constant, but not subqueries
+        let other: Vec<u8> = con.query_row("SELECT data FROM other", [], |row| row.get(0))?;
+        let mut stmt = con.prepare(
+            r#"SELECT stats.n,
+                      HYPERMINHASH_INTERSECTION(stats.data, ?1),
+                      HYPERMINHASH_INTERSECTION(stats.data, other.data),
+                      HYPERMINHASH_DESERIALIZE(HYPERMINHASH_ADD(stats.data, ?1)),
+                      HYPERMINHASH_DESERIALIZE(HYPERMINHASH_ADD(stats.data, other.data))
+               FROM stats, other ORDER BY stats.n"#,
+        )?;
+        let rows = stmt
+            .query_map([&other], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, f64>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, f64>(3)?,
+                    row.get::<_, f64>(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        assert_eq!(rows.len(), 3);
+        for (n, constant, joined, constant_add, joined_add) in rows {
+            assert_eq!(constant, joined);
+            assert_eq!(constant_add, joined_add);
+            let expected = (n - 250).max(0) as f64;
+            assert!((constant - expected).abs() < 0.05 * n as f64);
+        }
+
+        // Only constant arguments are kept, which takes a sketch's worth of memory;
+        // the others are read in place
+        con.query_row("SELECT HYPERMINHASH_MEMORY_LIMIT(20000)", [], |_| Ok(()))?;
+        con.query_row(
+            "SELECT SUM(HYPERMINHASH_INTERSECTION(stats.data, other.data)) FROM stats, other",
+            [],
+            |row| row.get::<_, f64>(0),
+        )?;
+        let r = con.query_row(
+            "SELECT SUM(HYPERMINHASH_INTERSECTION(stats.data, ?1)) FROM stats",
+            [&other],
+            |row| row.get::<_, f64>(0),
+        );
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_NOMEM));
+        Ok(())
+    }
+
+    test_wrong_type!(intersection_wrong_type, "HYPERMINHASH_INTERSECTION(1, 2)");
     test_bad_data!(
         intersection_bad_data,
         "HYPERMINHASH_INTERSECTION(X'00', X'00')"
     );
-}
 
-#[cfg(not(feature = "serialize"))]
-pub mod serialize_stub {
-    use super::*;
+    fn info(con: &rusqlite::Connection, expr: &str) -> rusqlite::Result<serde_json::Value> {
+        let s: String = con.query_row(
+            &format!("SELECT HYPERMINHASH_INFO({})", expr),
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        Ok(serde_json::from_str(&s).unwrap())
+    }
 
-    macro_rules! no_such_func {
-        ($name:ident, $func:literal) => {
-            #[test]
-            fn $name() -> rusqlite::Result<()> {
-                let con = init_db()?;
-                let r: rusqlite::Result<u8> =
-                    con.query_row(&format!("SELECT {}", $func), rusqlite::params![], |row| {
-                        row.get(0)
-                    });
-                expect_error_msg(r, "`serialize`-feature", "error not reported: ")
+    #[test]
+    fn info_zero() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let info = info(&con, "HYPERMINHASH_ZERO()")?;
+        assert_eq!(info["valid"], true);
+        assert_eq!(info["precision"], 14);
+        // Empty registers compress well, unless built without compression
+        if cfg!(feature = "compress") {
+            assert!(info["size"].as_u64().unwrap() < 100);
+            assert_eq!(info["compression"], "deflate");
+        } else {
+            assert_eq!(info["size"], 32768);
+            assert_eq!(info["compression"], serde_json::Value::Null);
+        }
+        assert_eq!(info["empty_registers"], 16384);
+        assert_eq!(info["histogram"][0], 16384);
+        assert_eq!(info["cardinality"], 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn info_serialize() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+        let mut stmt = con.prepare("INSERT INTO foo (id) VALUES (?1)")?;
+        for i in 0..100 {
+            stmt.execute([i])?;
+        }
+        let info = info(&con, "(SELECT HYPERMINHASH_SERIALIZE(id) FROM foo)")?;
+        assert_eq!(info["valid"], true);
+        let empty = info["empty_registers"].as_u64().unwrap();
+        assert!((16384 - 100..16384).contains(&empty));
+        let histogram_sum: u64 = info["histogram"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n.as_u64().unwrap())
+            .sum();
+        assert_eq!(histogram_sum, 16384);
+        let r = info["cardinality"].as_f64().unwrap();
+        assert!((1.0 - (r / 100.0)).abs() < 0.05);
+        Ok(())
+    }
+
+    #[test]
+    fn info_bad_data() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        // Malformed data is reported, not raised
+        let info = info(&con, "X'00'")?;
+        assert_eq!(info["valid"], false);
+        assert_eq!(info["size"], 1);
+        assert!(info["error"].is_string());
+        Ok(())
+    }
+
+    test_wrong_type!(info_wrong_type, "HYPERMINHASH_INFO(1)");
+
+    #[test]
+    fn info_bad_text() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let info = info(&con, "'foo!'")?;
+        assert_eq!(info["valid"], false);
+        assert!(info["error"].is_string());
+        Ok(())
+    }
+
+    fn text_db() -> rusqlite::Result<rusqlite::Connection> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE foo (id INT)", rusqlite::params![])?;
+        {
+            let mut stmt = con.prepare("INSERT INTO foo (id) VALUES (?1)")?;
+            for i in 0..1000 {
+                stmt.execute([i])?;
             }
-        };
+        }
+        Ok(con)
     }
 
-    no_such_func!(zero_returns_error, "hyperminhash_zero()");
-    no_such_func!(serialize_returns_error, "hyperminhash_serialize()");
-    no_such_func!(deserialize_returns_error, "hyperminhash_deserialize(X'00')");
-    no_such_func!(add_returns_error, "hyperminhash_add()");
-    no_such_func!(union_returns_error, "hyperminhash_union(X'00')");
-    no_such_func!(
-        intersection_returns_error,
-        "hyperminhash_intersection(X'00', X'00')"
+    #[test]
+    fn text_roundtrip() -> rusqlite::Result<()> {
+        let con = text_db()?;
+        for format in &["base64", "json"] {
+            let same: bool = con.query_row(
+                &format!(
+                    r#"WITH s AS (SELECT HYPERMINHASH_SERIALIZE(id) AS data FROM foo)
+                       SELECT HYPERMINHASH_FROM_TEXT(HYPERMINHASH_TO_TEXT(data, '{}')) = data
+                       FROM s"#,
+                    format
+                ),
+                rusqlite::params![],
+                |row| row.get(0),
+            )?;
+            assert!(same, "{} did not survive a roundtrip", format);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn text_json() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let s: String = con.query_row(
+            "SELECT HYPERMINHASH_TO_TEXT(HYPERMINHASH_ZERO(), 'json')",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        let v: serde_json::Value = serde_json::from_str(&s).unwrap();
+        assert_eq!(v["precision"], 14);
+        assert!(v["registers"].as_object().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn text_accepted() -> rusqlite::Result<()> {
+        let con = text_db()?;
+        con.execute(
+            r#"CREATE TABLE stats AS
+               SELECT HYPERMINHASH_TO_TEXT(HYPERMINHASH_SERIALIZE(id)) AS b64,
+                      HYPERMINHASH_TO_TEXT(HYPERMINHASH_SERIALIZE(id), 'json') AS json
+               FROM foo WHERE id < 750"#,
+            rusqlite::params![],
+        )?;
+        let (deser, add, union, intersection): (f64, f64, f64, f64) = con.query_row(
+            r#"SELECT HYPERMINHASH_DESERIALIZE(json),
+                      HYPERMINHASH_DESERIALIZE(HYPERMINHASH_ADD(b64, json)),
+                      HYPERMINHASH_DESERIALIZE((SELECT HYPERMINHASH_UNION(b64) FROM stats)),
+                      HYPERMINHASH_INTERSECTION(b64,
+                        (SELECT HYPERMINHASH_SERIALIZE(id) FROM foo WHERE id >= 250))
+               FROM stats"#,
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        for r in &[deser, add, union] {
+            assert!((1.0 - (r / 750.0)).abs() < 0.05);
+        }
+        assert!((1.0 - (intersection / 500.0)).abs() < 0.05);
+        Ok(())
+    }
+
+    test_bad_text!(deserialize_bad_text, "HYPERMINHASH_DESERIALIZE('foo!')");
+    test_bad_text!(add_bad_text, "HYPERMINHASH_ADD('foo!')");
+    test_bad_text!(union_bad_text, "HYPERMINHASH_UNION('foo!')");
+    test_bad_text!(
+        intersection_bad_text,
+        "HYPERMINHASH_INTERSECTION('foo!', 'bar!')"
+    );
+    test_bad_text!(
+        from_text_bad_json,
+        "HYPERMINHASH_FROM_TEXT('{\"precision\": 14, \"registers\": {\"16384\": 1}}')"
+    );
+    test_wrong_type!(to_text_wrong_type, "HYPERMINHASH_TO_TEXT(1)");
+    test_bad_data!(to_text_bad_data, "HYPERMINHASH_TO_TEXT(X'00')");
+
+    #[test]
+    fn registers() -> rusqlite::Result<()> {
+        let con = text_db()?;
+        con.execute(
+            "CREATE TABLE stats AS SELECT HYPERMINHASH_SERIALIZE(id) AS data FROM foo",
+            rusqlite::params![],
+        )?;
+        let (count, max_idx): (i64, i64) = con.query_row(
+            "SELECT COUNT(*), MAX(idx) FROM hyperminhash_registers((SELECT data FROM stats))",
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(count, 16384);
+        assert_eq!(max_idx, 16383);
+
+        // The registers agree with HYPERMINHASH_INFO()
+        let empty: i64 = con.query_row(
+            "SELECT COUNT(*) FROM stats, hyperminhash_registers(stats.data) WHERE lz = 0",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        let info = info(&con, "(SELECT data FROM stats)")?;
+        assert_eq!(info["empty_registers"], empty);
+        let max_minhash: i64 = con.query_row(
+            "SELECT MAX(minhash) FROM hyperminhash_registers((SELECT data FROM stats))",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(max_minhash < 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn registers_zero() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let nonzero: i64 = con.query_row(
+            "SELECT COUNT(*) FROM hyperminhash_registers(HYPERMINHASH_ZERO()) WHERE lz != 0 OR minhash != 0",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(nonzero, 0);
+        Ok(())
+    }
+
+    test_wrong_type!(
+        registers_wrong_type,
+        "COUNT(*) FROM hyperminhash_registers(1)"
+    );
+    test_bad_data!(
+        registers_bad_data,
+        "COUNT(*) FROM hyperminhash_registers(X'00')"
+    );
+
+    #[test]
+    fn to_text_bad_format() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let r: rusqlite::Result<String> = con.query_row(
+            "SELECT HYPERMINHASH_TO_TEXT(HYPERMINHASH_ZERO(), 'xml')",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "unknown text-format", "did not complain about format:")
+    }
+
+    const DAY: i64 = 86400;
+
+    /// Two weeks of daily events, each day's 100 users overlapping the previous day's
+    fn timeline_db() -> rusqlite::Result<rusqlite::Connection> {
+        let con = init_db()?;
+        con.execute(
+            "CREATE TABLE events (ts INT, user INT)",
+            rusqlite::params![],
+        )?;
+        {
+            let mut stmt = con.prepare("INSERT INTO events (ts, user) VALUES (?1, ?2)")?;
+            for day in 0..14 {
+                for user in day * 10..day * 10 + 100 {
+                    stmt.execute([day * DAY + user, user])?;
+                }
+            }
+        }
+        con.execute(
+            &format!(
+                "CREATE TABLE stats AS SELECT HYPERMINHASH_TIMELINE(ts, {}, user) AS tl FROM events",
+                DAY
+            ),
+            rusqlite::params![],
+        )?;
+        Ok(con)
+    }
+
+    fn range(con: &rusqlite::Connection, from: &str, to: &str) -> rusqlite::Result<f64> {
+        con.query_row(
+            &format!("SELECT HYPERMINHASH_RANGE(tl, {}, {}) FROM stats", from, to),
+            rusqlite::params![],
+            |row| row.get(0),
+        )
+    }
+
+    #[test]
+    fn timeline_range() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        // A single day's bucket is exactly the day's sketch
+        for day in &[0, 6, 13] {
+            let same: bool = con.query_row(
+                &format!(
+                    "SELECT HYPERMINHASH_RANGE(tl, {from}, {to}) = (SELECT HYPERMINHASH(user) FROM events WHERE ts BETWEEN {from} AND {to}) FROM stats",
+                    from = day * DAY,
+                    to = (day + 1) * DAY - 1
+                ),
+                rusqlite::params![],
+                |row| row.get(0),
+            )?;
+            assert!(same);
+        }
+        let week = range(&con, &(7 * DAY).to_string(), &(14 * DAY - 1).to_string())?;
+        assert!((1.0 - (week / 160.0)).abs() < 0.05);
+        let all = range(&con, "NULL", "NULL")?;
+        assert!((1.0 - (all / 230.0)).abs() < 0.05);
+        // Partially covered buckets are counted as a whole
+        assert_eq!(
+            range(&con, &(7 * DAY + 1).to_string(), &(8 * DAY).to_string())?,
+            range(&con, &(7 * DAY).to_string(), &(9 * DAY - 1).to_string())?
+        );
+        assert_eq!(range(&con, "1", "0")?, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_union() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        for merge in &[
+            "SELECT HYPERMINHASH_TIMELINE_UNION(tl) FROM halves",
+            "SELECT HYPERMINHASH_TIMELINE_ADD((SELECT tl FROM halves WHERE odd), (SELECT tl FROM halves WHERE NOT odd))",
+        ] {
+            let same: bool = con.query_row(
+                &format!(
+                    r#"WITH halves AS (SELECT user % 2 AS odd, HYPERMINHASH_TIMELINE(ts, {}, user) AS tl FROM events GROUP BY odd)
+                       SELECT ({}) = tl FROM stats"#,
+                    DAY, merge
+                ),
+                rusqlite::params![],
+                |row| row.get(0),
+            )?;
+            assert!(same, "{}", merge);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_width_mismatch() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let r: rusqlite::Result<Vec<u8>> = con.query_row(
+            "SELECT HYPERMINHASH_TIMELINE_ADD(tl, (SELECT HYPERMINHASH_TIMELINE(ts, 3600, user) FROM events)) FROM stats",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "bucket-widths differ", "did not complain about widths:")?;
+        let r: rusqlite::Result<Vec<u8>> = con.query_row(
+            "SELECT HYPERMINHASH_TIMELINE(ts, ts % 2 + 1, user) FROM events",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "bucket-width changed", "did not complain about widths:")
+    }
+
+    #[test]
+    fn timeline_expire() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let since = (7 * DAY + 1).to_string();
+        let expected = range(&con, &since, "NULL")?;
+        con.execute(
+            &format!(
+                "UPDATE stats SET tl = HYPERMINHASH_TIMELINE_EXPIRE(tl, {})",
+                since
+            ),
+            rusqlite::params![],
+        )?;
+        assert_eq!(range(&con, "NULL", "NULL")?, expected);
+        let info = info(&con, "(SELECT tl FROM stats)")?;
+        assert_eq!(info["format"], "timeline");
+        assert_eq!(info["buckets"], 7);
+        assert_eq!(info["first_bucket"], 7 * DAY);
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_compact() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let first_week = range(&con, "0", &(7 * DAY - 1).to_string())?;
+        con.execute(
+            &format!(
+                "UPDATE stats SET tl = HYPERMINHASH_TIMELINE_COMPACT(tl, {})",
+                7 * DAY
+            ),
+            rusqlite::params![],
+        )?;
+        assert_eq!(range(&con, "0", "0")?, first_week);
+        let info = info(&con, "(SELECT tl FROM stats)")?;
+        assert_eq!(info["buckets"], 2);
+        assert_eq!(info["bucket_width"], 7 * DAY);
+
+        let r: rusqlite::Result<Vec<u8>> = con.query_row(
+            "SELECT HYPERMINHASH_TIMELINE_COMPACT(tl, 1000) FROM stats",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "multiple of the old", "did not complain about width:")
+    }
+
+    #[test]
+    fn timeline_text() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let all = range(&con, "NULL", "NULL")?;
+        for format in &["base64", "json"] {
+            let text: String = con.query_row(
+                "SELECT HYPERMINHASH_TO_TEXT(tl, ?1) FROM stats",
+                [format],
+                |row| row.get(0),
+            )?;
+            let same: bool = con.query_row(
+                "SELECT HYPERMINHASH_FROM_TEXT(?1) = tl FROM stats",
+                [&text],
+                |row| row.get(0),
+            )?;
+            assert!(same, "{} did not round-trip", format);
+            // The timeline-functions take text-encodings as they take blobs
+            let r: f64 = con.query_row(
+                "SELECT HYPERMINHASH_RANGE(?1, NULL, NULL)",
+                [&text],
+                |row| row.get(0),
+            )?;
+            assert_eq!(r, all, "{}", format);
+            let same: bool = con.query_row(
+                "SELECT HYPERMINHASH_TIMELINE_ADD(?1, tl) = tl FROM stats",
+                [&text],
+                |row| row.get(0),
+            )?;
+            assert!(same, "{}", format);
+        }
+        let json: String = con.query_row(
+            "SELECT HYPERMINHASH_TO_TEXT(tl, 'json') FROM stats",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["bucket_width"], DAY);
+        assert_eq!(json["buckets"].as_object().unwrap().len(), 14);
+        assert!(json["buckets"][(13 * DAY).to_string()].is_object());
+        Ok(())
+    }
+
+    /// A timeline of `width`, with the given buckets of non-empty registers
+    fn raw_timeline(width: i64, buckets: &[(i64, &[(u16, u16)])]) -> Vec<u8> {
+        let mut buf = b"HMHT".to_vec();
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&width.to_le_bytes());
+        buf.extend_from_slice(&[0; 12]);
+        for (start, entries) in buckets {
+            buf.extend_from_slice(&start.to_le_bytes());
+            buf.extend_from_slice(&(entries.len() as u32 * 4).to_le_bytes());
+            for (idx, reg) in entries.iter() {
+                buf.extend_from_slice(&idx.to_le_bytes());
+                buf.extend_from_slice(&reg.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn timeline_sparse_buckets() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let range_of = |tl: &[u8], to: &str| -> rusqlite::Result<f64> {
+            con.query_row(
+                &format!("SELECT HYPERMINHASH_RANGE(?1, NULL, {})", to),
+                [tl],
+                |row| row.get(0),
+            )
+        };
+        let valid = raw_timeline(
+            10,
+            &[(0, &[(3, 0x0401), (7, 0x0802)]), (10, &[(5, 0x0401)])],
+        );
+        assert!(range_of(&valid, "9")? > 0.0);
+        assert!(range_of(&valid, "NULL")? > range_of(&valid, "9")?);
+
+        // Only the buckets in range are decoded
+        let unordered = raw_timeline(
+            10,
+            &[(0, &[(3, 0x0401)]), (10, &[(7, 0x0401), (5, 0x0401)])],
+        );
+        assert_eq!(
+            range_of(&unordered, "9")?,
+            range_of(&raw_timeline(10, &[(0, &[(3, 0x0401)])]), "NULL")?
+        );
+        expect_error_msg(
+            range_of(&unordered, "NULL"),
+            "malformed sparse bucket",
+            "accepted unordered registers",
+        )?;
+        let validation: Option<String> =
+            con.query_row("SELECT HYPERMINHASH_VALIDATE(?1)", [&unordered], |row| {
+                row.get(0)
+            })?;
+        assert!(validation.unwrap().contains("malformed sparse bucket"));
+        for (bad, what) in &[
+            (
+                raw_timeline(10, &[(0, &[(16384, 0x0401)])]),
+                "index out of range",
+            ),
+            (raw_timeline(10, &[(0, &[(3, 0)])]), "empty register"),
+        ] {
+            expect_error_msg(range_of(bad, "NULL"), "malformed sparse bucket", what)?;
+        }
+        let mut truncated = valid.clone();
+        truncated.truncate(truncated.len() - 2);
+        expect_error_msg(
+            range_of(&truncated, "9"),
+            "truncated bucket",
+            "accepted a truncated bucket",
+        )
+    }
+
+    #[test]
+    fn timeline_is_not_a_sketch() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let r: rusqlite::Result<f64> = con.query_row(
+            "SELECT HYPERMINHASH_DESERIALIZE(tl) FROM stats",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "is a timeline", "did not complain about timeline:")?;
+        let r: rusqlite::Result<f64> = con.query_row(
+            "SELECT HYPERMINHASH_RANGE(HYPERMINHASH_ZERO(), NULL, NULL)",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(
+            r,
+            "not a serialized timeline",
+            "did not complain about sketch:",
+        )
+    }
+
+    #[test]
+    fn timeline_signing() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let timeline = |con: &rusqlite::Connection| -> rusqlite::Result<Vec<u8>> {
+            con.query_row(
+                &format!(
+                    "SELECT HYPERMINHASH_TIMELINE(ts, {}, user) FROM events",
+                    DAY
+                ),
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        let range_of = |tl: &[u8]| -> rusqlite::Result<f64> {
+            con.query_row("SELECT HYPERMINHASH_RANGE(?1, NULL, NULL)", [tl], |row| {
+                row.get(0)
+            })
+        };
+        let of = |expr: &str, tl: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            con.query_row(&format!("SELECT {}(?1)", expr), [tl], |row| row.get(0))
+                .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        let unsigned = timeline(&con)?;
+        let all = range_of(&unsigned)?;
+
+        let checked = of("HYPERMINHASH_CHECKSUM", &unsigned)?;
+        assert_eq!(checked.len(), unsigned.len() + 8);
+        assert_eq!(range_of(&checked)?, all);
+        let mut flipped = checked.clone();
+        flipped[100] ^= 0x01;
+        expect_error_msg(
+            range_of(&flipped),
+            "checksum does not match",
+            "accepted a flipped bit",
+        )?;
+
+        con.query_row("SELECT HYPERMINHASH_HMAC_KEY('key')", [], |_| Ok(()))?;
+        let signed = of("HYPERMINHASH_SIGN", &checked)?;
+        assert_eq!(signed.len(), unsigned.len() + 36);
+        assert_eq!(range_of(&signed)?, all);
+        let info = info(&con, &format!("X'{}'", hex(&signed)))?;
+        assert_eq!(info["integrity"], "hmac-sha256");
+        assert_eq!(of("HYPERMINHASH_VERIFY", &signed)?, signed);
+        let r = of("HYPERMINHASH_VERIFY", &unsigned);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "not signed", "verified an unsigned timeline")?;
+        let mut forged = signed.clone();
+        forged[100] ^= 0x01;
+        expect_error_msg(
+            of("HYPERMINHASH_VERIFY", &forged),
+            "signature does not match",
+            "verified a forged timeline",
+        )?;
+        // Results carry no trailer of their own
+        let added: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_TIMELINE_ADD(?1, ?1)",
+            [&signed],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&added), unsigned);
+        Ok(())
+    }
+
+    #[test]
+    fn memory_limit_covers_loaded_sketches() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let limit = |limit: Option<i64>| {
+            con.query_row("SELECT HYPERMINHASH_MEMORY_LIMIT(?1)", [limit], |_| Ok(()))
+        };
+        let dense = sketch_of(10000);
+        let queries = [
+            "SELECT HYPERMINHASH_ADD(?1)",
+            "SELECT HYPERMINHASH_ADD(?1, ?1)",
+            "SELECT HYPERMINHASH_INSERT(?1, 1)",
+            r#"WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 9999)
+               SELECT HYPERMINHASH_FROM_JSON((SELECT JSON_GROUP_ARRAY(i) FROM n WHERE ?1 NOT NULL))"#,
+        ];
+        limit(Some(20_000))?;
+        for query in &queries {
+            let r = con.query_row(query, [&dense], |row| {
+                row.get::<_, rusqlite::types::Value>(0)
+            });
+            assert_eq!(
+                error_code(&r),
+                Some(rusqlite::ffi::SQLITE_NOMEM),
+                "{} exceeded the limit",
+                query
+            );
+        }
+        limit(None)?;
+        for query in &queries {
+            con.query_row(query, [&dense], |_| Ok(()))?;
+        }
+
+        // Constant arguments kept by one statement count against the budget of
+        // all others
+        limit(Some(70_000))?;
+        let mut stmt =
+            con.prepare("SELECT HYPERMINHASH_ADD(?1, column1) FROM (VALUES (?1), (?1), (?1))")?;
+        let mut rows = stmt.query([&sketch_of(100)])?;
+        rows.next()?;
+        rows.next()?;
+        let add = || con.query_row("SELECT HYPERMINHASH_ADD(?1)", [&dense], |_| Ok(()));
+        assert_eq!(error_code(&add()), Some(rusqlite::ffi::SQLITE_NOMEM));
+        drop(rows);
+        add()?;
+        limit(None)
+    }
+
+    #[test]
+    fn timeline_limits() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        // A bucket per point in time
+        let r: rusqlite::Result<i64> = con.query_row(
+            r#"WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+               SELECT LENGTH(HYPERMINHASH_TIMELINE(i, 1, i)) FROM n"#,
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "too many buckets", "did not limit buckets:")?;
+
+        // Buckets are charged to the memory-limit
+        con.query_row("SELECT HYPERMINHASH_MEMORY_LIMIT(2000)", [], |_| Ok(()))?;
+        let r: rusqlite::Result<Vec<u8>> = con.query_row(
+            &format!(
+                "SELECT HYPERMINHASH_TIMELINE(ts, {}, user) FROM events",
+                DAY
+            ),
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_NOMEM));
+        let r = range(&con, "NULL", "NULL");
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_NOMEM));
+        con.query_row("SELECT HYPERMINHASH_MEMORY_LIMIT(NULL)", [], |_| Ok(()))?;
+        assert!(range(&con, "NULL", "NULL")? > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_without_rows() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let tl: Option<Vec<u8>> = con.query_row(
+            "SELECT HYPERMINHASH_TIMELINE(ts, 1, user) FROM events WHERE user < 0",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(tl.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn columns_serialize() -> rusqlite::Result<()> {
+        let con = text_db()?;
+        let s: String = con.query_row(
+            "SELECT HYPERMINHASH_COLUMNS_SERIALIZE(id, id % 10) FROM foo",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        let blobs: serde_json::Value = serde_json::from_str(&s).unwrap();
+        // Each column's sketch is the same as serializing the column on it's own
+        for (col, expr) in &[("0", "id"), ("1", "id % 10")] {
+            let same: bool = con.query_row(
+                &format!(
+                    "SELECT HYPERMINHASH_FROM_TEXT(?1) = (SELECT HYPERMINHASH_SERIALIZE({}) FROM foo)",
+                    expr
+                ),
+                [blobs[*col].as_str().unwrap()],
+                |row| row.get(0),
+            )?;
+            assert!(same, "{}", expr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn grouped_serialize() -> rusqlite::Result<()> {
+        let con = text_db()?;
+        let s: String = con.query_row(
+            "SELECT HYPERMINHASH_GROUPED_SERIALIZE(id % 2, id) FROM foo",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        let blobs: serde_json::Value = serde_json::from_str(&s).unwrap();
+        // Each key's sketch is the same as serializing the key's rows on their own
+        for key in &["0", "1"] {
+            let same: bool = con.query_row(
+                "SELECT HYPERMINHASH_FROM_TEXT(?1) = (SELECT HYPERMINHASH_SERIALIZE(id) FROM foo WHERE id % 2 = ?2)",
+                rusqlite::params![blobs[*key].as_str().unwrap(), key.parse::<i64>().unwrap()],
+                |row| row.get(0),
+            )?;
+            assert!(same, "{}", key);
+        }
+        // ... and can be unioned later on
+        let union: f64 = con.query_row(
+            "SELECT HYPERMINHASH_DESERIALIZE(HYPERMINHASH_ADD(?1, ?2))",
+            [blobs["0"].as_str().unwrap(), blobs["1"].as_str().unwrap()],
+            |row| row.get(0),
+        )?;
+        assert!((1.0 - (union / 1000.0)).abs() < 0.05);
+        Ok(())
+    }
+
+    #[test]
+    fn from_json() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        // The same as counting the values from a column
+        let same: bool = con.query_row(
+            r#"SELECT HYPERMINHASH_FROM_JSON('[1, 2.5, "foo", null, true]') = (SELECT HYPERMINHASH_SERIALIZE(v) FROM (SELECT 1 AS v UNION ALL SELECT 2.5 UNION ALL SELECT 'foo' UNION ALL SELECT NULL UNION ALL SELECT 1) WHERE v IS NOT NULL)"#,
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+        let same: bool = con.query_row(
+            "SELECT HYPERMINHASH_FROM_JSON(NULL) = HYPERMINHASH_ZERO()",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+        Ok(())
+    }
+
+    fn sketch_of(n: u64) -> Vec<u8> {
+        let mut sketch = Sketch::default();
+        for i in 0..n {
+            sketch.add(i);
+        }
+        let mut buf = Vec::new();
+        sketch.save(&mut buf).unwrap();
+        buf
+    }
+
+    fn set_register(buf: &mut [u8], idx: usize, lz: u16, minhash: u16) {
+        buf[idx * 2..idx * 2 + 2].copy_from_slice(&((lz << 10) | minhash).to_le_bytes());
+    }
+
+    fn validate(con: &rusqlite::Connection, buf: &[u8]) -> rusqlite::Result<Option<String>> {
+        con.query_row(
+            "SELECT HYPERMINHASH_VALIDATE(?1)",
+            rusqlite::params![buf],
+            |row| row.get(0),
+        )
+    }
+
+    #[test]
+    fn validate_honest_sketches() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        for n in (0..200).chain([1000, 5000, 20000, 100_000, 300_000].iter().copied()) {
+            assert_eq!(validate(&con, &sketch_of(n))?, None, "{}", n);
+        }
+        let timeline: Option<String> = con.query_row(
+            "SELECT HYPERMINHASH_VALIDATE(HYPERMINHASH_TIMELINE(v, 10, v)) FROM (SELECT 1 AS v UNION ALL SELECT 25)",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(timeline, None);
+        Ok(())
+    }
+
+    #[test]
+    fn validate_crafted_sketches() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let mut buf = sketch_of(1000);
+        set_register(&mut buf, 7, 60, 0);
+        let problem = validate(&con, &buf)?.unwrap();
+        assert!(problem.contains("register 7"), "{}", problem);
+
+        let mut buf = sketch_of(0);
+        set_register(&mut buf, 3, 0, 1);
+        let problem = validate(&con, &buf)?.unwrap();
+        assert!(problem.contains("register 3 is empty"), "{}", problem);
+
+        // A few saturated registers, inflating any union
+        let mut buf = sketch_of(1000);
+        for idx in (0..100).map(|i| i * 97) {
+            set_register(&mut buf, idx, 51, 1);
+        }
+        let problem = validate(&con, &buf)?.unwrap();
+        assert!(problem.contains("leading-zero count"), "{}", problem);
+
+        // All of them
+        let mut buf = sketch_of(0);
+        for idx in 0..1 << 14 {
+            set_register(&mut buf, idx, 51, 1);
+        }
+        assert!(validate(&con, &buf)?.is_some());
+
+        // Malformed data is reported as well
+        assert!(validate(&con, &[0])?.is_some());
+        let text: Option<String> = con.query_row(
+            "SELECT HYPERMINHASH_VALIDATE('foo!')",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(text.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn strict_mode() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE stats (data BLOB)", rusqlite::params![])?;
+        let mut crafted = sketch_of(1000);
+        for idx in (0..2000).map(|i| i * 8) {
+            set_register(&mut crafted, idx, 51, 1);
+        }
+        con.execute(
+            "INSERT INTO stats (data) VALUES (?1), (?2)",
+            rusqlite::params![sketch_of(1000), crafted],
+        )?;
+        let union = |expr: &str| -> rusqlite::Result<f64> {
+            con.query_row(
+                &format!(
+                    "SELECT HYPERMINHASH_DESERIALIZE(HYPERMINHASH_UNION({})) FROM stats",
+                    expr
+                ),
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+        };
+        // The crafted sketch inflates the union
+        assert!(union("data")? > 2000.0);
+
+        let r = union("HYPERMINHASH_STRICT(data)");
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "suspicious sketch", "accepted a crafted sketch")?;
+        for expr in &[
+            "HYPERMINHASH_DESERIALIZE(HYPERMINHASH_STRICT(data))",
+            "HYPERMINHASH_ADD(HYPERMINHASH_STRICT(data))",
+            "HYPERMINHASH_INTERSECTION(data, HYPERMINHASH_STRICT(data))",
+            "HYPERMINHASH_TO_TEXT(HYPERMINHASH_STRICT(data))",
+        ] {
+            let r: rusqlite::Result<i64> = con.query_row(
+                &format!("SELECT COUNT({}) FROM stats", expr),
+                rusqlite::params![],
+                |row| row.get(0),
+            );
+            assert_eq!(
+                error_code(&r),
+                Some(rusqlite::ffi::SQLITE_FORMAT),
+                "{}",
+                expr
+            );
+        }
+        // Honest sketches pass as they are
+        let same: bool = con.query_row(
+            "SELECT HYPERMINHASH_STRICT(data) = data FROM stats WHERE rowid = 1",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+        let null: Option<Vec<u8>> =
+            con.query_row("SELECT HYPERMINHASH_STRICT(NULL)", [], |row| row.get(0))?;
+        assert_eq!(null, None);
+        Ok(())
+    }
+
+    fn hex(buf: &[u8]) -> String {
+        buf.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn cardinality_of(con: &rusqlite::Connection, buf: &[u8]) -> rusqlite::Result<f64> {
+        con.query_row(
+            "SELECT HYPERMINHASH_DESERIALIZE(?1)",
+            rusqlite::params![buf],
+            |row| row.get(0),
+        )
+    }
+
+    /// The empty sketch, as it is before compression
+    fn zero_blob(con: &rusqlite::Connection) -> rusqlite::Result<Vec<u8>> {
+        let buf: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_ZERO()", rusqlite::params![], |row| {
+                row.get(0)
+            })?;
+        Ok(inflated(&buf))
+    }
+
+    #[test]
+    fn checksum() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let checksum = |buf: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            con.query_row("SELECT HYPERMINHASH_CHECKSUM(?1)", [buf], |row| row.get(0))
+                .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        let zero = zero_blob(&con)?;
+        assert_eq!(zero.len(), 32768);
+        // The CRC-32 of 32768 zero-bytes, as zlib computes it
+        let buf = checksum(&zero)?;
+        assert_eq!(hex(&buf[32768..]), "484d4843a6fc1f01");
+        assert_eq!(checksum(&buf)?, buf);
+
+        let buf = checksum(&sketch_of(1000))?;
+        assert_eq!(buf.len(), 32776);
+        assert!((1.0 - cardinality_of(&con, &buf)? / 1000.0).abs() < 0.05);
+        let info: String =
+            con.query_row("SELECT HYPERMINHASH_INFO(?1)", [&buf], |row| row.get(0))?;
+        let info: serde_json::Value = serde_json::from_str(&info).unwrap();
+        assert_eq!(info["integrity"], "crc32");
+        assert_eq!(info["valid"], true);
+
+        // The base64-encoding carries the checksum as well
+        let decoded: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_FROM_TEXT(HYPERMINHASH_TO_TEXT(?1))",
+            [&buf],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&decoded), buf);
+
+        let mut flipped = buf.clone();
+        flipped[1234] ^= 0x04;
+        let r = cardinality_of(&con, &flipped);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "checksum does not match", "accepted a flipped bit")?;
+        let problem = validate(&con, &flipped)?.unwrap();
+        assert!(problem.contains("checksum does not match"), "{}", problem);
+        let r = cardinality_of(&con, &buf[..32770]);
+        expect_error_msg(r, "expected 32768 bytes", "accepted a truncated blob")?;
+        expect_error_msg(
+            checksum(&flipped),
+            "checksum does not match",
+            "checked a flipped bit",
+        )?;
+
+        // Checksums are checked, but results carry none of their own
+        let added: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_ADD(?1)", [&buf], |row| row.get(0))?;
+        assert_eq!(inflated(&added), &buf[..32768]);
+        let null: Option<Vec<u8>> =
+            con.query_row("SELECT HYPERMINHASH_CHECKSUM(NULL)", [], |row| row.get(0))?;
+        assert_eq!(null, None);
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_inplace() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE stats (data BLOB)", rusqlite::params![])?;
+        con.query_row("SELECT HYPERMINHASH_HMAC_KEY('key')", [], |_| Ok(()))?;
+        con.execute(
+            "INSERT INTO stats (data) VALUES
+             (HYPERMINHASH_CHECKSUM(HYPERMINHASH_ZERO())),
+             (HYPERMINHASH_SIGN(HYPERMINHASH_ZERO())),
+             (HYPERMINHASH_ZERO())",
+            rusqlite::params![],
+        )?;
+        let inplace = |rowid: i64| -> rusqlite::Result<i64> {
+            con.query_row(
+                "SELECT HYPERMINHASH_INSERT_INPLACE(NULL, 'stats', 'data', ?1, 'foo')",
+                [rowid],
+                |row| row.get(0),
+            )
+        };
+        expect_error_msg(
+            inplace(1),
+            "can't be updated in place",
+            "broke the checksum",
+        )?;
+        expect_error_msg(
+            inplace(2),
+            "can't be updated in place",
+            "broke the signature",
+        )?;
+        // The HMAC-key does not matter to sketches without a trailer
+        assert_eq!(inplace(3)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn hmac_signing() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let set_key = |key: Option<&[u8]>| -> rusqlite::Result<bool> {
+            con.query_row("SELECT HYPERMINHASH_HMAC_KEY(?1)", [key], |row| row.get(0))
+        };
+        let of = |expr: &str, buf: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            con.query_row(&format!("SELECT {}(?1)", expr), [buf], |row| row.get(0))
+                .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        let unsigned = sketch_of(1000);
+        for expr in &["HYPERMINHASH_SIGN", "HYPERMINHASH_VERIFY"] {
+            expect_error_msg(of(expr, &unsigned), "no HMAC-key is set", expr)?;
+        }
+        assert!(!set_key(Some(b"key"))?);
+        // As of Python's hmac.new(b'key', bytes(32768), 'sha256')
+        let signed = of("HYPERMINHASH_SIGN", &zero_blob(&con)?)?;
+        assert_eq!(
+            hex(&signed[32768..]),
+            "484d4853fe727c7c3a90dc8239b0d79f458fbd88c8b1761dcfda2971d3ac6b692ea63a16"
+        );
+        assert_eq!(of("HYPERMINHASH_VERIFY", &signed)?, signed);
+        assert_eq!(of("HYPERMINHASH_SIGN", &signed)?, signed);
+        assert_eq!(cardinality_of(&con, &signed)?, 0.0);
+
+        let r = of("HYPERMINHASH_VERIFY", &unsigned);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "sketch is not signed", "verified an unsigned sketch")?;
+        expect_error_msg(
+            of("HYPERMINHASH_VERIFY", &signed[..32768]),
+            "sketch is not signed",
+            "verified a stripped signature",
+        )?;
+        let mut forged = signed.clone();
+        forged[0] = 0xff;
+        expect_error_msg(
+            of("HYPERMINHASH_VERIFY", &forged),
+            "signature does not match",
+            "verified a forged sketch",
+        )?;
+        expect_error_msg(
+            of("HYPERMINHASH_SIGN", &forged),
+            "signature does not match",
+            "signed a forged sketch",
+        )?;
+        // Other functions read signed sketches without checking the signature
+        assert!(cardinality_of(&con, &forged).is_ok());
+
+        // Keys longer than SHA-256's block are hashed first
+        assert!(set_key(Some(&[b'k'; 100]))?);
+        let long = of("HYPERMINHASH_SIGN", &zero_blob(&con)?)?;
+        assert_eq!(
+            hex(&long[32772..]),
+            "dd2993b1796cae47090853dc7445c78f1c13694b47e932aabb348d6876c73815"
+        );
+        expect_error_msg(
+            of("HYPERMINHASH_VERIFY", &signed),
+            "signature does not match",
+            "verified a sketch signed with another key",
+        )?;
+        // A checksum replaces the signature
+        assert_eq!(
+            hex(&of("HYPERMINHASH_CHECKSUM", &signed)?[32768..]),
+            "484d4843a6fc1f01"
+        );
+
+        assert!(set_key(None)?);
+        assert_eq!(cardinality_of(&con, &signed)?, 0.0);
+        let r = set_key(Some(b""));
+        expect_error_msg(r, "the key is empty", "accepted an empty key")
+    }
+
+    #[test]
+    fn only_key_dependent_functions_are_not_deterministic() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE sketches (data BLOB)", [])?;
+        for expr in [
+            "HYPERMINHASH_ZERO()",
+            "HYPERMINHASH_ADD(data)",
+            "HYPERMINHASH_DESERIALIZE(data)",
+            "HYPERMINHASH_INTERSECTION(data, data)",
+            "HYPERMINHASH_INSERT(data, 1)",
+            "HYPERMINHASH_TO_TEXT(data)",
+            "HYPERMINHASH_INFO(data)",
+            "HYPERMINHASH_STRICT(data)",
+            "HYPERMINHASH_CHECKSUM(data)",
+            "HYPERMINHASH_UPGRADE(data)",
+        ] {
+            con.execute(&format!("CREATE INDEX idx ON sketches({})", expr), [])?;
+            con.execute("DROP INDEX idx", [])?;
+        }
+        // An index would keep results computed with keys long gone
+        for expr in [
+            "HYPERMINHASH_KEYED_ZERO()",
+            "HYPERMINHASH_KEYED_INSERT(data, 1)",
+            "HYPERMINHASH_SIGN(data)",
+            "HYPERMINHASH_VERIFY(data)",
+        ] {
+            let r = con.execute(&format!("CREATE INDEX idx ON sketches({})", expr), []);
+            expect_error_msg(
+                r,
+                "non-deterministic functions prohibited",
+                "allowed a key-dependent function in an index",
+            )?;
+        }
+        Ok(())
+    }
+
+    fn keyed_db(secret: Option<&str>) -> rusqlite::Result<rusqlite::Connection> {
+        let con = init_db()?;
+        if let Some(secret) = secret {
+            let previous: bool =
+                con.query_row("SELECT HYPERMINHASH_HASH_KEY(?1)", [secret], |row| {
+                    row.get(0)
+                })?;
+            assert!(!previous);
+        }
+        con.execute_batch(
+            "CREATE TABLE users (id INTEGER);
+             WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 999)
+             INSERT INTO users SELECT i FROM n;",
+        )?;
+        Ok(con)
+    }
+
+    fn serialized_users(con: &rusqlite::Connection) -> rusqlite::Result<Vec<u8>> {
+        let buf: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_SERIALIZE(id) FROM users", [], |row| {
+                row.get(0)
+            })?;
+        Ok(inflated(&buf))
+    }
+
+    fn keyed_users(con: &rusqlite::Connection) -> rusqlite::Result<Vec<u8>> {
+        let buf: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_KEYED_SERIALIZE(id) FROM users",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(inflated(&buf))
+    }
+
+    #[test]
+    fn keyed_hashing() -> rusqlite::Result<()> {
+        let keyed = keyed_db(Some("secret"))?;
+        let sketch = keyed_users(&keyed)?;
+        // As of Python's hmac.new(b'secret', b'hyperminhash key-id', 'sha256')
+        assert_eq!(hex(&sketch[32768..]), "484d484b0e06f13d78a361ae");
+        let plain = keyed_db(None)?;
+        let unkeyed = serialized_users(&plain)?;
+        assert_eq!(unkeyed.len(), 32768);
+        assert_ne!(sketch[..32768], unkeyed[..]);
+        // Only the keyed functions use the key, which they require
+        assert_eq!(serialized_users(&keyed)?, unkeyed);
+        expect_error_msg(
+            keyed_users(&plain),
+            "no hash-key is set",
+            "hashed keyed rows without a key",
+        )?;
+        // The same secret hashes alike on any connection
+        assert_eq!(keyed_users(&keyed_db(Some("secret"))?)?, sketch);
+        let zero: Vec<u8> =
+            keyed.query_row("SELECT HYPERMINHASH_KEYED_ZERO()", [], |row| row.get(0))?;
+        assert_eq!(inflated(&zero)[32768..], sketch[32768..]);
+
+        // Counting needs no key, adding rows does
+        assert!((1.0 - cardinality_of(&plain, &sketch)? / 1000.0).abs() < 0.05);
+        let insert =
+            |con: &rusqlite::Connection, func: &str, buf: &[u8]| -> rusqlite::Result<Vec<u8>> {
+                con.query_row(&format!("SELECT {}(?1, 5)", func), [buf], |row| row.get(0))
+                    .map(|buf: Vec<u8>| inflated(&buf))
+            };
+        expect_error_msg(
+            insert(&keyed, "HYPERMINHASH_INSERT", &sketch),
+            "sketch is keyed, rows have to be hashed with it's key",
+            "probed a keyed sketch without the key",
+        )?;
+        expect_error_msg(
+            insert(&keyed, "HYPERMINHASH_KEYED_INSERT", &unkeyed),
+            "sketch is not keyed, rows have to be hashed without a key",
+            "added keyed rows to an unkeyed sketch",
+        )?;
+        expect_error_msg(
+            insert(
+                &keyed_db(Some("other"))?,
+                "HYPERMINHASH_KEYED_INSERT",
+                &sketch,
+            ),
+            "sketch was hashed with a different key",
+            "added rows to a sketch of another key",
+        )?;
+        // Already counted, hashed as KEYED_SERIALIZE() did
+        assert_eq!(
+            insert(&keyed, "HYPERMINHASH_KEYED_INSERT", &sketch)?,
+            sketch
+        );
+
+        let r = keyed.query_row("SELECT HYPERMINHASH_HASH_KEY('')", [], |row| {
+            row.get::<_, bool>(0)
+        });
+        expect_error_msg(r, "the key is empty", "accepted an empty key")?;
+        let cleared: bool =
+            keyed.query_row("SELECT HYPERMINHASH_HASH_KEY(NULL)", [], |row| row.get(0))?;
+        assert!(cleared);
+        expect_error_msg(
+            keyed_users(&keyed),
+            "no hash-key is set",
+            "hashed keyed rows with a cleared key",
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn keyed_unions() -> rusqlite::Result<()> {
+        let con = keyed_db(None)?;
+        let a = keyed_users(&keyed_db(Some("secret"))?)?;
+        let b = keyed_users(&keyed_db(Some("other"))?)?;
+        let unkeyed = serialized_users(&con)?;
+        let add = |x: &[u8], y: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            con.query_row("SELECT HYPERMINHASH_ADD(?1, ?2)", [x, y], |row| row.get(0))
+                .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        // Sketches of the same key are merged without knowing it
+        assert_eq!(add(&a, &a)?, a);
+        expect_error_msg(
+            add(&a, &b),
+            "sketches were hashed with different keys",
+            "merged sketches of different keys",
+        )?;
+        expect_error_msg(
+            add(&unkeyed, &a),
+            "sketches were hashed with different keys",
+            "merged a keyed and an unkeyed sketch",
+        )?;
+        let r = con.query_row(
+            "SELECT HYPERMINHASH_INTERSECTION(?1, ?2)",
+            [&a, &b],
+            |row| row.get::<_, f64>(0),
+        );
+        expect_error_msg(
+            r,
+            "different keys",
+            "intersected sketches of different keys",
+        )?;
+
+        con.execute("CREATE TABLE sketches (data BLOB)", [])?;
+        let union = |data: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            con.execute("INSERT INTO sketches (data) VALUES (?1)", [data])?;
+            con.query_row("SELECT HYPERMINHASH_UNION(data) FROM sketches", [], |row| {
+                row.get(0)
+            })
+            .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        assert_eq!(union(&a)?, a);
+        assert_eq!(union(&a)?, a);
+        expect_error_msg(
+            union(&b),
+            "sketches were hashed with different keys",
+            "unioned sketches of different keys",
+        )?;
+
+        // The key-id is shown and survives the text-encodings
+        assert_eq!(
+            info(&con, &format!("X'{}'", hex(&a)))?["key_id"],
+            "ae61a3783df1060e"
+        );
+        let from_json: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_FROM_TEXT(HYPERMINHASH_TO_TEXT(?1, 'json'))",
+            [&a],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&from_json), a);
+        Ok(())
+    }
+
+    #[test]
+    fn keyed_producers() -> rusqlite::Result<()> {
+        let con = keyed_db(Some("secret"))?;
+        let sketch = keyed_users(&con)?;
+        con.execute_batch(
+            "CREATE TABLE stats (data BLOB);
+             INSERT INTO stats (data) VALUES (HYPERMINHASH_KEYED_ZERO());",
+        )?;
+        con.query_row(
+            "SELECT SUM(HYPERMINHASH_INSERT_INPLACE(NULL, 'stats', 'data', 1, id)) FROM users",
+            [],
+            |_| Ok(()),
+        )?;
+        let stored: Vec<u8> = con.query_row("SELECT data FROM stats", [], |row| row.get(0))?;
+        assert_eq!(stored, sketch);
+        let column: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_FROM_TEXT(JSON_EXTRACT(HYPERMINHASH_KEYED_COLUMNS_SERIALIZE(id), '$.0'))
+             FROM users",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&column), sketch);
+        let grouped: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_FROM_TEXT(JSON_EXTRACT(HYPERMINHASH_KEYED_GROUPED_SERIALIZE(1, id), '$.1'))
+             FROM users",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&grouped), sketch);
+        let from_json: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_KEYED_FROM_JSON('[1]')", [], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(inflated(&from_json)[32768..], sketch[32768..]);
+
+        // The key-tag is covered by the checksum, which follows it
+        let checked: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_CHECKSUM(?1)", [&sketch], |row| {
+                row.get(0)
+            })?;
+        let checked = inflated(&checked);
+        assert_eq!(checked[..32780], sketch[..]);
+        assert!(hex(&checked[32780..]).starts_with("484d4843"));
+        let inserted: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_KEYED_INSERT(?1, 5)",
+            [&checked],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&inserted), sketch);
+
+        // Timelines carry the key-id as well, and are hashed with the key
+        let timeline = |func: &str| -> rusqlite::Result<Vec<u8>> {
+            con.query_row(
+                &format!("SELECT {}(id, 10000, id) FROM users", func),
+                [],
+                |row| row.get(0),
+            )
+        };
+        let keyed = timeline("HYPERMINHASH_KEYED_TIMELINE")?;
+        let (count, expected): (f64, f64) = con.query_row(
+            "SELECT HYPERMINHASH_RANGE(?1, NULL, NULL), HYPERMINHASH_DESERIALIZE(?2)",
+            rusqlite::params![&keyed, &sketch],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(count, expected);
+        let key_id = info(&con, &format!("X'{}'", hex(&sketch)))?["key_id"].clone();
+        assert!(key_id.is_string());
+        assert_eq!(
+            info(&con, &format!("X'{}'", hex(&keyed)))?["key_id"],
+            key_id
+        );
+
+        let plain = timeline("HYPERMINHASH_TIMELINE")?;
+        assert_eq!(
+            info(&con, &format!("X'{}'", hex(&plain)))?["key_id"],
+            serde_json::Value::Null
+        );
+        let r = con.query_row(
+            "SELECT LENGTH(HYPERMINHASH_TIMELINE_ADD(?1, ?2))",
+            [&keyed, &plain],
+            |row| row.get::<_, i64>(0),
+        );
+        expect_error_msg(r, "different keys", "merged timelines of different keys")
+    }
+
+    /// A deflate-stream of a single stored block
+    fn stored_block(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut buf = b"HMHZ".to_vec();
+        buf.push(0x01);
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&(!len).to_le_bytes());
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn upgrade() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        // The upgraded sketch, as it is before compression
+        let upgrade = |buf: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            let buf: Vec<u8> =
+                con.query_row("SELECT HYPERMINHASH_UPGRADE(?1)", [buf], |row| row.get(0))?;
+            Ok(inflated(&buf))
+        };
+        let legacy = sketch_of(1000);
+        // Nothing to do but compressing without checksums or signatures
+        assert_eq!(upgrade(&legacy)?, legacy);
+        let null: Option<Vec<u8>> =
+            con.query_row("SELECT HYPERMINHASH_UPGRADE(NULL)", [], |row| row.get(0))?;
+        assert_eq!(null, None);
+        let from_text: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_UPGRADE(HYPERMINHASH_TO_TEXT(?1, 'json'))",
+            [&legacy],
+            |row| row.get(0),
+        )?;
+        assert_eq!(inflated(&from_text), legacy);
+
+        // Checksums and signatures are kept
+        let of = |expr: &str, buf: &[u8]| -> rusqlite::Result<Vec<u8>> {
+            con.query_row(&format!("SELECT {}(?1)", expr), [buf], |row| row.get(0))
+                .map(|buf: Vec<u8>| inflated(&buf))
+        };
+        let checked = of("HYPERMINHASH_CHECKSUM", &legacy)?;
+        assert_eq!(upgrade(&checked)?, checked);
+        con.query_row("SELECT HYPERMINHASH_HMAC_KEY('key')", [], |_| Ok(()))?;
+        let signed = of("HYPERMINHASH_SIGN", &legacy)?;
+        assert_eq!(upgrade(&signed)?, signed);
+        let mut flipped = checked.clone();
+        flipped[10] ^= 0x01;
+        let r = upgrade(&flipped);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "checksum does not match", "upgraded a flipped bit")
+    }
+
+    #[test]
+    fn upgrade_only_signs_on_request() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.query_row("SELECT HYPERMINHASH_HMAC_KEY('key')", [], |_| Ok(()))?;
+        let integrity = |expr: &str, buf: &[u8]| -> rusqlite::Result<Option<String>> {
+            con.query_row(
+                &format!(
+                    "SELECT JSON_EXTRACT(HYPERMINHASH_INFO({}), '$.integrity')",
+                    expr
+                ),
+                [buf],
+                |row| row.get(0),
+            )
+        };
+        let legacy = sketch_of(1000);
+        let signed: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_SIGN(?1)", [&legacy], |row| row.get(0))?;
+        let mut stripped = inflated(&signed);
+        stripped.truncate(legacy.len());
+
+        // A key being set signs nothing, neither unsigned nor stripped sketches
+        assert_eq!(integrity("HYPERMINHASH_UPGRADE(?1)", &legacy)?, None);
+        assert_eq!(integrity("HYPERMINHASH_UPGRADE(?1, 0)", &stripped)?, None);
+        let r = integrity("HYPERMINHASH_VERIFY(HYPERMINHASH_UPGRADE(?1))", &stripped);
+        expect_error_msg(r, "sketch is not signed", "signed a stripped sketch")?;
+        con.execute("CREATE TABLE stats (data BLOB)", rusqlite::params![])?;
+        con.execute("INSERT INTO stats (data) VALUES (?1)", [&stripped])?;
+        upgrade_column(&con, 10)?;
+        let unsigned: Option<String> = con.query_row(
+            "SELECT JSON_EXTRACT(HYPERMINHASH_INFO(data), '$.integrity') FROM stats",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(unsigned, None);
+
+        // Unless asked to
+        assert_eq!(
+            integrity("HYPERMINHASH_VERIFY(HYPERMINHASH_UPGRADE(?1, 1))", &legacy)?,
+            Some("hmac-sha256".to_owned())
+        );
+        con.query_row(
+            "SELECT HYPERMINHASH_UPGRADE_COLUMN('stats', 'data', 10, 1)",
+            rusqlite::params![],
+            |_| Ok(()),
+        )?;
+        let verified: Option<String> = con.query_row(
+            "SELECT JSON_EXTRACT(HYPERMINHASH_INFO(HYPERMINHASH_VERIFY(data)), '$.integrity') FROM stats",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(verified, Some("hmac-sha256".to_owned()));
+
+        // A forged signature isn't signed over
+        let mut forged = inflated(&signed);
+        let len = forged.len();
+        forged[len - 1] ^= 0x01;
+        let r = integrity("HYPERMINHASH_UPGRADE(?1, 1)", &forged);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "signature does not match", "signed a forged sketch")?;
+
+        // Nor anything without a key
+        con.query_row("SELECT HYPERMINHASH_HMAC_KEY(NULL)", [], |_| Ok(()))?;
+        let r = integrity("HYPERMINHASH_UPGRADE(?1, 1)", &legacy);
+        expect_error_msg(r, "no HMAC-key is set", "signed without a key")?;
+        let r = con.query_row(
+            "SELECT HYPERMINHASH_UPGRADE_COLUMN('stats', 'data', 10, 1)",
+            rusqlite::params![],
+            |row| row.get::<_, String>(0),
+        );
+        expect_error_msg(r, "no HMAC-key is set", "signed a column without a key")
+    }
+
+    fn upgrade_column(
+        con: &rusqlite::Connection,
+        batch: i64,
+    ) -> rusqlite::Result<serde_json::Value> {
+        let report: String = con.query_row(
+            "SELECT HYPERMINHASH_UPGRADE_COLUMN('stats', 'data', ?1)",
+            [batch],
+            |row| row.get(0),
+        )?;
+        Ok(serde_json::from_str(&report).unwrap())
+    }
+
+    #[test]
+    fn upgrade_whole_column() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE stats (data)", rusqlite::params![])?;
+        for n in 0..10 {
+            con.execute("INSERT INTO stats (data) VALUES (?1)", [sketch_of(n * 100)])?;
+        }
+        con.execute(
+            "INSERT INTO stats (data) VALUES (NULL)",
+            rusqlite::params![],
+        )?;
+        con.execute(
+            "INSERT INTO stats (data) VALUES (HYPERMINHASH_TO_TEXT(?1))",
+            [sketch_of(5)],
+        )?;
+        let text_size: i64 = con.query_row(
+            "SELECT LENGTH(data) FROM stats WHERE rowid = 12",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        con.execute(
+            "INSERT INTO stats (rowid, data) VALUES (?1, ?2)",
+            rusqlite::params![i64::MIN, sketch_of(7)],
+        )?;
+        let before: f64 = con.query_row(
+            "SELECT HYPERMINHASH_DESERIALIZE(HYPERMINHASH_UNION(data)) FROM stats WHERE data NOT NULL",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+
+        // All sketches get compressed; without compression, only the text is
+        // converted and the blobs are up to date
+        let report = upgrade_column(&con, 3)?;
+        assert_eq!(report["rows"], 12);
+        if cfg!(feature = "compress") {
+            assert_eq!(report["converted"], 12);
+            assert!(report["bytes_saved"].as_i64().unwrap() > 11 * 16384);
+        } else {
+            assert_eq!(report["converted"], 1);
+            assert_eq!(report["bytes_saved"], text_size - 32768);
+        }
+
+        let report = upgrade_column(&con, 1000)?;
+        assert_eq!(report["converted"], 0);
+        assert_eq!(report["bytes_saved"], 0);
+
+        // Checksums are kept
+        con.execute(
+            "UPDATE stats SET data = HYPERMINHASH_CHECKSUM(data)",
+            rusqlite::params![],
+        )?;
+        let report = upgrade_column(&con, 3)?;
+        assert_eq!(report["rows"], 12);
+        assert_eq!(report["converted"], 0);
+
+        let (checked, after): (i64, f64) = con.query_row(
+            r#"SELECT SUM(JSON_EXTRACT(HYPERMINHASH_INFO(data), '$.integrity') = 'crc32'),
+                      HYPERMINHASH_DESERIALIZE(HYPERMINHASH_UNION(data))
+               FROM stats WHERE data NOT NULL"#,
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(checked, 12);
+        assert_eq!(before, after);
+        Ok(())
+    }
+
+    #[test]
+    fn upgrade_column_rolls_back() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        con.execute("CREATE TABLE stats (data BLOB)", rusqlite::params![])?;
+        // Text-encodings are always converted
+        for n in 0..5 {
+            con.execute(
+                "INSERT INTO stats (data) VALUES (HYPERMINHASH_TO_TEXT(?1))",
+                [sketch_of(n * 100)],
+            )?;
+        }
+        con.execute(
+            "INSERT INTO stats (data) VALUES (X'00')",
+            rusqlite::params![],
+        )?;
+        let r = upgrade_column(&con, 2);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "row 6: malformed value", "upgraded a broken sketch")?;
+        let texts: i64 = con.query_row(
+            "SELECT COUNT(*) FROM stats WHERE TYPEOF(data) = 'text'",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert_eq!(texts, 5);
+
+        // Works within a transaction of it's own, too
+        con.execute("DELETE FROM stats WHERE rowid = 6", rusqlite::params![])?;
+        con.execute_batch("BEGIN")?;
+        assert_eq!(upgrade_column(&con, 2)?["converted"], 5);
+        con.execute_batch("ROLLBACK")?;
+        assert_eq!(upgrade_column(&con, 2)?["converted"], 5);
+
+        let r = con.query_row(
+            "SELECT HYPERMINHASH_UPGRADE_COLUMN('stats', 'data', 0)",
+            rusqlite::params![],
+            |row| row.get::<_, String>(0),
+        );
+        expect_error_msg(r, "batch-size must be positive", "accepted an empty batch")
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn compression() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let zero: Vec<u8> = con.query_row("SELECT HYPERMINHASH_ZERO()", [], |row| row.get(0))?;
+        assert!(
+            zero.starts_with(b"HMHZ") && zero.len() < 100,
+            "{}",
+            zero.len()
+        );
+        assert_eq!(cardinality_of(&con, &zero)?, 0.0);
+
+        con.execute("CREATE TABLE stats (data BLOB)", rusqlite::params![])?;
+        con.execute(
+            r#"INSERT INTO stats (data)
+               WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3000)
+               SELECT HYPERMINHASH_SERIALIZE(i) FROM n GROUP BY i % 3"#,
+            rusqlite::params![],
+        )?;
+        let (size, union, intersection): (i64, f64, f64) = con.query_row(
+            r#"SELECT MAX(LENGTH(data)),
+                      HYPERMINHASH_DESERIALIZE(HYPERMINHASH_UNION(data)),
+                      HYPERMINHASH_INTERSECTION(MIN(data), MIN(data))
+               FROM stats"#,
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        assert!(size < 32768 / 2, "{}", size);
+        assert!((1.0 - union / 3000.0).abs() < 0.05);
+        assert!((1.0 - intersection / 1000.0).abs() < 0.05);
+        let (info, size): (String, i64) = con.query_row(
+            "SELECT HYPERMINHASH_INFO(data), LENGTH(data) FROM stats LIMIT 1",
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let info: serde_json::Value = serde_json::from_str(&info).unwrap();
+        assert_eq!(info["compression"], "deflate");
+        assert_eq!(info["size"].as_i64(), Some(size));
+        let same: bool = con.query_row(
+            "SELECT HYPERMINHASH_FROM_TEXT(HYPERMINHASH_TO_TEXT(data)) = data FROM stats LIMIT 1",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+
+        // Random registers don't compress and are kept as they are
+        let mut rnd = rand::thread_rng();
+        let noise = (0..32768).map(|_| rnd.gen()).collect::<Vec<u8>>();
+        let buf: Vec<u8> =
+            con.query_row("SELECT HYPERMINHASH_ADD(?1)", [&noise], |row| row.get(0))?;
+        assert_eq!(buf, noise);
+
+        // The checksum is compressed along with the registers
+        let mut checked: Vec<u8> = con.query_row(
+            "SELECT HYPERMINHASH_CHECKSUM(HYPERMINHASH_ZERO())",
+            [],
+            |row| row.get(0),
+        )?;
+        assert!(checked.starts_with(b"HMHZ"));
+        assert_eq!(inflated(&checked).len(), 32776);
+        let last = checked.len() - 1;
+        checked[last] ^= 0x01;
+        let r = cardinality_of(&con, &checked);
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        Ok(())
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn compression_of_timelines() -> rusqlite::Result<()> {
+        let con = timeline_db()?;
+        let (compressed, validation): (Vec<u8>, Option<String>) = con.query_row(
+            "SELECT tl, HYPERMINHASH_VALIDATE(tl) FROM stats",
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert!(compressed.starts_with(b"HMHZ"));
+        // 14 buckets of 100 users each are stored sparse, not as 14 full sketches
+        let inflated_len = inflated(&compressed).len();
+        assert!(inflated_len < 14 * 1024, "{}", inflated_len);
+        assert!(compressed.len() < inflated_len, "{}", compressed.len());
+        assert_eq!(validation, None);
+        let info = info(&con, "(SELECT tl FROM stats)")?;
+        assert_eq!(info["format"], "timeline");
+        assert_eq!(info["compression"], "deflate");
+        let all = range(&con, "NULL", "NULL")?;
+        let same: bool = con.query_row(
+            "SELECT HYPERMINHASH_RANGE(?1, NULL, NULL) = ?2",
+            rusqlite::params![inflated(&compressed), all],
+            |row| row.get(0),
+        )?;
+        assert!(same);
+        Ok(())
+    }
+
+    #[test]
+    fn compression_bad_data() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let r = cardinality_of(&con, &stored_block(&[0; 40000]));
+        assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_FORMAT));
+        expect_error_msg(r, "can't inflate", "inflated a bomb")?;
+        let r = cardinality_of(&con, b"HMHZ\x01\x00");
+        expect_error_msg(r, "can't inflate", "inflated a truncated stream")?;
+        // A valid stream of the wrong size
+        let r = cardinality_of(&con, &stored_block(&[0; 100]));
+        expect_error_msg(r, "expected 32768 bytes", "accepted a short sketch")?;
+        // Validation also accepts timelines, which may inflate to more
+        assert_eq!(
+            validate(&con, &stored_block(&[0; 40000]))?.as_deref(),
+            Some("malformed value: expected 32768 bytes, found 40000")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_inplace() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        // Compressed sketches are written back uncompressed on their first update,
+        // and updated in place from then on
+        con.execute("CREATE TABLE stats (data BLOB)", rusqlite::params![])?;
+        con.execute(
+            "INSERT INTO stats (data) VALUES (?1)",
+            [&stored_block(&[0; 32768])],
+        )?;
+        for expected in &[1, 0] {
+            let changed: i64 = con.query_row(
+                "SELECT HYPERMINHASH_INSERT_INPLACE(NULL, 'stats', 'data', 1, 'foo')",
+                rusqlite::params![],
+                |row| row.get(0),
+            )?;
+            assert_eq!(changed, *expected);
+        }
+        let (stored, expected): (Vec<u8>, Vec<u8>) = con.query_row(
+            "SELECT data, HYPERMINHASH_INSERT(HYPERMINHASH_ZERO(), 'foo') FROM stats",
+            rusqlite::params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(stored, inflated(&expected));
+
+        // Sketches carrying a checksum are refused, compressed or not
+        con.execute(
+            "UPDATE stats SET data = ?1",
+            [&stored_block(
+                &zero_blob(&con)?
+                    .into_iter()
+                    .chain(*b"HMHC\x00\x00\x00\x00")
+                    .collect::<Vec<_>>(),
+            )],
+        )?;
+        let r: rusqlite::Result<i64> = con.query_row(
+            "SELECT HYPERMINHASH_INSERT_INPLACE(NULL, 'stats', 'data', 1, 'foo')",
+            rusqlite::params![],
+            |row| row.get(0),
+        );
+        expect_error_msg(r, "can't be updated in place", "broke the checksum")
+    }
+
+    /// Results are not compressed without the `compress`-feature, but compressed
+    /// sketches are read all the same
+    #[cfg(not(feature = "compress"))]
+    #[test]
+    fn compression_turned_off() -> rusqlite::Result<()> {
+        let con = init_db()?;
+        let zero: Vec<u8> = con.query_row("SELECT HYPERMINHASH_ZERO()", [], |row| row.get(0))?;
+        assert_eq!(zero.len(), 32768);
+        assert_eq!(cardinality_of(&con, &stored_block(&[0; 32768]))?, 0.0);
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "serialize"))]
+pub mod serialize_stub {
+    use super::*;
+
+    macro_rules! no_such_func {
+        ($name:ident, $func:literal) => {
+            #[test]
+            fn $name() -> rusqlite::Result<()> {
+                let con = init_db()?;
+                let r: rusqlite::Result<u8> =
+                    con.query_row(&format!("SELECT {}", $func), rusqlite::params![], |row| {
+                        row.get(0)
+                    });
+                assert_eq!(error_code(&r), Some(rusqlite::ffi::SQLITE_ERROR));
+                expect_error_msg(r, "`serialize`-feature", "error not reported: ")
+            }
+        };
+    }
+
+    no_such_func!(zero_returns_error, "hyperminhash_zero()");
+    no_such_func!(serialize_returns_error, "hyperminhash_serialize()");
+    no_such_func!(deserialize_returns_error, "hyperminhash_deserialize(X'00')");
+    no_such_func!(add_returns_error, "hyperminhash_add()");
+    no_such_func!(union_returns_error, "hyperminhash_union(X'00')");
+    no_such_func!(
+        intersection_returns_error,
+        "hyperminhash_intersection(X'00', X'00')"
+    );
+    no_such_func!(info_returns_error, "hyperminhash_info(X'00')");
+    no_such_func!(to_text_returns_error, "hyperminhash_to_text(X'00')");
+    no_such_func!(from_text_returns_error, "hyperminhash_from_text('')");
+    no_such_func!(insert_returns_error, "hyperminhash_insert(NULL, 1)");
+    no_such_func!(validate_returns_error, "hyperminhash_validate(X'00')");
+    no_such_func!(strict_returns_error, "hyperminhash_strict(1)");
+    no_such_func!(
+        insert_inplace_returns_error,
+        "hyperminhash_insert_inplace(NULL, 'foo', 'bar', 1, 1)"
+    );
+    no_such_func!(
+        registers_returns_error,
+        "* FROM hyperminhash_registers(X'00')"
+    );
+    no_such_func!(timeline_returns_error, "hyperminhash_timeline(1, 1)");
+    no_such_func!(
+        timeline_union_returns_error,
+        "hyperminhash_timeline_union(X'00')"
+    );
+    no_such_func!(
+        timeline_add_returns_error,
+        "hyperminhash_timeline_add(X'00')"
+    );
+    no_such_func!(range_returns_error, "hyperminhash_range(X'00', 1, 2)");
+    no_such_func!(
+        timeline_expire_returns_error,
+        "hyperminhash_timeline_expire(X'00', 1)"
+    );
+    no_such_func!(
+        timeline_compact_returns_error,
+        "hyperminhash_timeline_compact(X'00', 1)"
+    );
+    no_such_func!(
+        columns_serialize_returns_error,
+        "hyperminhash_columns_serialize(1)"
+    );
+    no_such_func!(
+        grouped_serialize_returns_error,
+        "hyperminhash_grouped_serialize(1, 1)"
+    );
+    no_such_func!(from_json_returns_error, "hyperminhash_from_json('[]')");
+    no_such_func!(checksum_returns_error, "hyperminhash_checksum(1)");
+    no_such_func!(sign_returns_error, "hyperminhash_sign(X'00')");
+    no_such_func!(verify_returns_error, "hyperminhash_verify(X'00')");
+    no_such_func!(
+        keyed_serialize_returns_error,
+        "hyperminhash_keyed_serialize(1)"
+    );
+    no_such_func!(hmac_key_returns_error, "hyperminhash_hmac_key('key')");
+    no_such_func!(hash_key_returns_error, "hyperminhash_hash_key('key')");
+    no_such_func!(upgrade_returns_error, "hyperminhash_upgrade(X'00')");
+    no_such_func!(
+        upgrade_column_returns_error,
+        "hyperminhash_upgrade_column('t', 'c')"
     );
 }